@@ -3,4 +3,5 @@ pub mod brightness;
 pub mod install;
 pub mod monitor;
 pub mod theme;
+pub mod uninstall;
 pub mod volume;
@@ -1,194 +1,607 @@
-use anyhow::{anyhow, Result};
-use clap::Args;
-use std::{
-    io::{Cursor, Read},
-    path::{Path, PathBuf},
-    process::{Command, Stdio},
-};
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, Subcommand};
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Mutex, thread};
 
 use crate::{
-    config::profiles::{
-        module_config::{ModuleConfig, ModuleStep},
-        profiles_config::ProfilesConfig,
-        tools_config::ToolConfig,
-    },
-    utils::command_extensions::CommandExtensions,
+    config::{profiles::profiles_config::ProfilesConfig, Config},
+    modules::{host::Host, menu::Menu},
+    utils::archive_utils::sha256_hex,
 };
 
+use install_hooks::{run_hooks, InstallHooks};
+use install_log::InstallLog;
+use install_state::InstallState;
+use package_cache::PackageCache;
+use profile_module::{ProfileModule, ProfileStep, StepAction, StepStatus};
+use snapshot::HostSnapshot;
+use validation::Validation;
+use variables::Variables;
+use written_files::WrittenFiles;
+
+pub mod install_hooks;
+pub mod install_log;
+pub mod install_state;
+pub mod package_cache;
+pub mod profile_module;
+pub mod snapshot;
+pub mod validation;
+pub mod variables;
+pub mod written_files;
+
 #[derive(Args)]
 pub struct InstallCommand {
-    #[arg(short, long)]
-    profile: Option<String>,
+    #[command(subcommand)]
+    command: Option<InstallSubcommands>,
+
+    /// Profiles to install, defaults to `default_profile`
+    #[arg(short, long, global = true)]
+    profile: Vec<String>,
+    #[arg(global = true)]
     profiles_path: Option<PathBuf>,
+
+    /// Print the install plan without executing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Download archive packages and print their sha256 digests instead of installing
+    #[arg(long)]
+    print_checksums: bool,
+
+    /// Fail instead of downloading archives that are not in the cache
+    #[arg(long)]
+    offline: bool,
+
+    /// Re-run steps even if they were already completed with the same inputs
+    #[arg(long)]
+    force: bool,
+
+    /// Continue the last run from its first failed or unfinished step
+    #[arg(long, conflicts_with_all = ["from", "only"])]
+    resume: bool,
+
+    /// Skip modules resolved before the given one
+    #[arg(long, value_name = "MODULE", conflicts_with = "only")]
+    from: Option<String>,
+
+    /// Install only the given modules, without their dependencies
+    #[arg(long, value_name = "MODULE")]
+    only: Vec<String>,
+
+    /// Pick the modules to install from a menu, their dependencies are added automatically
+    #[arg(long, conflicts_with_all = ["resume", "from", "only"])]
+    select: bool,
+
+    /// Maximum number of parallel steps running at once
+    #[arg(short, long, default_value_t = 4)]
+    jobs: usize,
 }
 
-#[derive(Debug)]
-enum ProfileModule {
-    Tool {
-        name: String,
-        config: ToolConfig,
-        packages: Vec<String>,
-    },
-    Script {
-        name: String,
-        script: String,
-    },
-    Zip {
-        name: String,
-        target_dir: PathBuf,
-        packages: Vec<String>,
+#[derive(Subcommand)]
+enum InstallSubcommands {
+    /// Print the install plan without executing anything
+    Plan,
+    /// Show which steps of the profile are satisfied on this machine without changing anything
+    Status,
+    /// List profiles with their fully expanded module lists
+    ListProfiles,
+    /// Check profiles, tools and modules definitions without installing anything
+    Validate,
+    /// Describe the packages and enabled user units of this machine as a module
+    Snapshot {
+        /// Name of the generated module, defaults to the hostname
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Directory to write `<name>.toml` and `<name>-tools.toml` to instead of printing them
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Compare the machine with the selected profiles instead of generating a module
+        #[arg(long)]
+        diff: bool,
     },
 }
 
-pub struct InstallCommandHandler {}
+pub struct InstallCommandHandler {
+    resource_root_dir: PathBuf,
+}
 
 impl InstallCommandHandler {
-    pub fn create() -> Self {
-        Self {}
+    pub fn create(config: &Config) -> Self {
+        Self {
+            resource_root_dir: config.general.resource_root_dir.clone(),
+        }
     }
 
     pub fn handle(self, cmd: &InstallCommand) -> Result<()> {
-        let profile = self.resolve_profile(cmd)?;
-        dbg!(&profile);
+        match &cmd.command {
+            Some(InstallSubcommands::Validate) => return Self::validate(cmd),
+            Some(InstallSubcommands::Snapshot { name, output, diff }) => {
+                return Self::snapshot(cmd, name, output, *diff)
+            }
+            _ => {}
+        }
 
+        let config = ProfilesConfig::parse(&cmd.profiles_path)?;
+        if matches!(cmd.command, Some(InstallSubcommands::ListProfiles)) {
+            return Self::list_profiles(&config);
+        }
+
+        let mut state = InstallState::load(&self.resource_root_dir)?;
+
+        let profile_names = if cmd.resume && cmd.profile.is_empty() {
+            state
+                .last_run()
+                .map(|run| run.profiles.clone())
+                .ok_or(anyhow!("there is no previous install run to resume"))?
+        } else {
+            config.profile_names(&cmd.profile)?
+        };
+
+        let profile = if cmd.select {
+            Self::pick_modules(&config, &profile_names)?
+        } else {
+            Self::select_modules(ProfileModule::resolve(&config, &profile_names)?, cmd)?
+        };
+
+        if cmd.dry_run || matches!(cmd.command, Some(InstallSubcommands::Plan)) {
+            Self::print_plan(&profile);
+            return Ok(());
+        }
+
+        if matches!(cmd.command, Some(InstallSubcommands::Status)) {
+            Self::print_status(&profile);
+            return Ok(());
+        }
+
+        let cache = PackageCache::new(&self.resource_root_dir, cmd.offline);
+        if cmd.print_checksums {
+            return Self::print_checksums(&profile, &cache);
+        }
+
+        if cmd.offline {
+            Self::ensure_cached(&profile, &cache)?;
+        }
+
+        if cmd.resume {
+            match state.last_run().and_then(|run| run.pending.first()) {
+                Some(key) => println!("resuming install from {}", key),
+                None => {
+                    println!("last install run has already finished");
+                    return Ok(());
+                }
+            }
+        } else {
+            let keys = profile
+                .iter()
+                .flat_map(|module| module.steps.iter().map(|step| module.step_key(step)))
+                .collect();
+            state.start_run(&profile_names, keys)?;
+        }
+
+        let hooks = InstallHooks::resolve(&config, &profile_names)?;
+        let files = WrittenFiles::load(&self.resource_root_dir)?;
+        let log = InstallLog::create(&self.resource_root_dir, "install")?;
+        let result = hooks
+            .run_before()
+            .and_then(|()| Self::install(&profile, &mut state, &cache, &files, &log, cmd))
+            .and_then(|()| hooks.run_after());
+        log.print_summary();
+        hooks.finish(&result, &log);
+
+        result
+    }
+
+    fn install(
+        profile: &[ProfileModule],
+        state: &mut InstallState,
+        cache: &PackageCache,
+        files: &WrittenFiles,
+        log: &InstallLog,
+        cmd: &InstallCommand,
+    ) -> Result<()> {
         for module in profile {
-            module.execute()?;
+            // adjacent parallel steps form a group that runs concurrently
+            let mut groups: Vec<Vec<&ProfileStep>> = vec![];
+            for step in &module.steps {
+                match groups.last_mut() {
+                    Some(group) if step.parallel && group[0].parallel => group.push(step),
+                    _ => groups.push(vec![step]),
+                }
+            }
+
+            // hooks are left out for modules without anything to install
+            let mut hooks_run = false;
+            for group in groups {
+                let mut steps = vec![];
+                for step in group {
+                    if Self::should_run(module, step, state, log, cmd)? {
+                        steps.push(step);
+                    }
+                }
+
+                if !steps.is_empty() && !hooks_run {
+                    run_hooks(&format!("{} before", module.name), &module.before, &[])?;
+                    hooks_run = true;
+                }
+
+                match steps.as_slice() {
+                    [] => {}
+                    [step] => Self::run_step(module, step, state, cache, files, log)?,
+                    _ => Self::run_parallel(module, &steps, state, cache, files, log, cmd.jobs)?,
+                }
+            }
+
+            if hooks_run {
+                run_hooks(&format!("{} after", module.name), &module.after, &[])?;
+            }
         }
 
         Ok(())
     }
 
-    fn resolve_profile(self, cmd: &InstallCommand) -> Result<Vec<ProfileModule>> {
-        let config = ProfilesConfig::parse(&cmd.profiles_path)?;
-        dbg!(&config);
+    fn should_run(
+        module: &ProfileModule,
+        step: &ProfileStep,
+        state: &mut InstallState,
+        log: &InstallLog,
+        cmd: &InstallCommand,
+    ) -> Result<bool> {
+        let key = module.step_key(step);
+
+        if cmd.resume && !state.is_pending(&key) {
+            return Ok(false);
+        }
+
+        if !cmd.force && !step.tracks_upstream() && state.is_completed(&key, &step.fingerprint()) {
+            println!("[{}] already completed, skipping", step.name);
+            log.skip(&key);
+            state.skip(&key)?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn run_step(
+        module: &ProfileModule,
+        step: &ProfileStep,
+        state: &mut InstallState,
+        cache: &PackageCache,
+        files: &WrittenFiles,
+        log: &InstallLog,
+    ) -> Result<()> {
+        let key = module.step_key(step);
+
+        if let Err(err) = log.run(&key, |output| step.execute(cache, files, output)) {
+            state.fail(&key)?;
+            if step.policy.continue_on_error {
+                println!("[{}] failed, continuing: {:#}", step.name, err);
+                return Ok(());
+            }
+
+            return Err(err.context(format!(
+                "{} step failed, continue with `pde install --resume`",
+                key
+            )));
+        }
+
+        state.complete(&key, &step.fingerprint())
+    }
+
+    /// Runs the steps on a bounded pool of workers and reports all failures once
+    /// every step has finished
+    fn run_parallel(
+        module: &ProfileModule,
+        steps: &[&ProfileStep],
+        state: &mut InstallState,
+        cache: &PackageCache,
+        files: &WrittenFiles,
+        log: &InstallLog,
+        jobs: usize,
+    ) -> Result<()> {
+        let queue = Mutex::new(steps.iter());
+        let state = Mutex::new(state);
+        let failures = Mutex::new(vec![]);
+
+        thread::scope(|scope| {
+            for _ in 0..jobs.clamp(1, steps.len()) {
+                scope.spawn(|| {
+                    while let Some(step) = Self::next_step(&queue) {
+                        let key = module.step_key(step);
+                        let result = log.run(&key, |output| step.execute(cache, files, output));
+                        let result = result.and_then(|_| {
+                            let mut state = state.lock().expect("install state lock poisoned");
+                            state.complete(&key, &step.fingerprint())
+                        });
+
+                        if let Err(err) = result {
+                            let mut state = state.lock().expect("install state lock poisoned");
+                            let message = format!("{:#}", err);
+                            let failure = match state.fail(&key) {
+                                Ok(()) => format!("{}: {}", key, message.trim_end()),
+                                Err(state_err) => {
+                                    format!("{}: {} ({:#})", key, message.trim_end(), state_err)
+                                }
+                            };
 
-        let profile_name = cmd
-            .profile.as_ref()
-            .or(config.default_profile.as_ref())
-            .ok_or(
-                anyhow!("profile is not selected. Either provide it as cli arg or define default_profile field in profiles config")
-            )?;
+                            if step.policy.continue_on_error {
+                                println!("[{}] failed, continuing: {}", step.name, failure);
+                                continue;
+                            }
+                            failures
+                                .lock()
+                                .expect("failures lock poisoned")
+                                .push(failure);
+                        }
+                    }
+                });
+            }
+        });
+
+        let failures = failures.into_inner().expect("failures lock poisoned");
+        if !failures.is_empty() {
+            bail!(
+                "{}\n{} of {} parallel steps failed, continue with `pde install --resume`",
+                failures.join("\n"),
+                failures.len(),
+                steps.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn next_step<'a>(
+        queue: &Mutex<std::slice::Iter<'a, &'a ProfileStep>>,
+    ) -> Option<&'a ProfileStep> {
+        queue
+            .lock()
+            .expect("step queue lock poisoned")
+            .next()
+            .copied()
+    }
+
+    fn snapshot(
+        cmd: &InstallCommand,
+        name: &Option<String>,
+        output: &Option<PathBuf>,
+        diff: bool,
+    ) -> Result<()> {
+        let snapshot = HostSnapshot::capture()?;
 
-        let profile = config
-            .profiles
-            .get(profile_name)
-            .ok_or(anyhow!("'{}' profile defintion not found", profile_name))?;
+        if diff {
+            let config = ProfilesConfig::parse(&cmd.profiles_path)?;
+            let profile_names = config.profile_names(&cmd.profile)?;
+            snapshot.print_diff(&ProfileModule::resolve(&config, &profile_names)?);
+            return Ok(());
+        }
+
+        let name = match name {
+            Some(name) => name.clone(),
+            None => Host::hostname()?,
+        };
+        let module = snapshot.module_toml(&name)?;
+        let tools = snapshot.tools_toml()?;
+
+        let Some(dir) = output else {
+            println!("# tools definitions\n{}", tools);
+            println!("# {}.toml\n{}", name, module);
+            return Ok(());
+        };
 
-        let modules: Result<Vec<&ModuleConfig>> = profile
+        let module_path = dir.join(format!("{}.toml", name));
+        let tools_path = dir.join(format!("{}-tools.toml", name));
+        for path in [&module_path, &tools_path] {
+            if path.exists() {
+                bail!("{} already exists", path.display());
+            }
+        }
+
+        fs::create_dir_all(dir)?;
+        fs::write(&module_path, module)?;
+        fs::write(&tools_path, tools)?;
+        println!("module written to {}", module_path.display());
+        println!(
+            "tools written to {}, merge them into your tools definitions",
+            tools_path.display()
+        );
+
+        Ok(())
+    }
+
+    fn validate(cmd: &InstallCommand) -> Result<()> {
+        let validation = Validation::run(&cmd.profiles_path);
+        for warning in &validation.warnings {
+            println!("warning: {}", warning);
+        }
+        for error in &validation.errors {
+            println!("error: {}", error);
+        }
+
+        if !validation.errors.is_empty() {
+            bail!("validation failed with {} errors", validation.errors.len());
+        }
+
+        println!("profiles are valid");
+        Ok(())
+    }
+
+    fn pick_modules(
+        config: &ProfilesConfig,
+        profile_names: &[String],
+    ) -> Result<Vec<ProfileModule>> {
+        let modules = config.resolve_modules(profile_names)?;
+        let items = modules
             .iter()
-            .map(|module| {
-                config
-                    .modules
-                    .get(module)
-                    .ok_or(anyhow!("{} module not found", module))
+            .map(|module| match &module.description {
+                Some(description) => format!("{} - {}", module.name, description),
+                None => module.name.clone(),
             })
-            .collect();
+            .collect::<Vec<_>>();
 
-        let resolved: Result<Vec<ProfileModule>, _> = modules?
+        let picked = Menu::multi_select("modules", &items)?
             .into_iter()
-            .flat_map(|cfg| cfg.steps.clone())
-            .map(|cfg| match cfg {
-                ModuleStep::Tool {
-                    tool,
-                    packages,
-                    name,
-                } => config
-                    .tools
-                    .get(&tool)
-                    .cloned()
-                    .ok_or(anyhow!("{} tool not found", &tool))
-                    .map(|tool_config| ProfileModule::Tool {
-                        name,
-                        config: tool_config,
-                        packages: packages.clone(),
-                    }),
-                ModuleStep::Script { script, name } => Ok(ProfileModule::Script { name, script }),
-                ModuleStep::Zip {
-                    name,
-                    extract_zip_to,
-                    packages,
-                } => Ok(ProfileModule::Zip {
-                    name,
-                    target_dir: extract_zip_to.clone(),
-                    packages: packages.clone(),
-                }),
-            })
-            .collect();
+            .map(|idx| modules[idx].name.clone())
+            .collect::<Vec<_>>();
+        if picked.is_empty() {
+            bail!("no modules selected");
+        }
 
-        resolved
+        let variables = Variables::new(config, profile_names)?;
+        ProfileModule::from_configs(config, config.resolve_module_names(&picked)?, &variables)
     }
-}
 
-impl ProfileModule {
-    pub fn execute(&self) -> Result<()> {
-        match self {
-            Self::Tool {
-                name,
-                config,
-                packages,
-            } => Self::execute_tool(name, config, packages),
-            Self::Script { name, script } => Self::execute_script(name, &script),
-            Self::Zip {
-                name,
-                target_dir,
-                packages,
-            } => Self::execute_zip(name, target_dir, packages),
-        }
-    }
-
-    fn execute_tool(name: &str, tool: &ToolConfig, packages: &Vec<String>) -> Result<()> {
-        println!("[{}] executing tool: {}", name, &tool.cmd);
-        if tool.batching {
-            let mut cmd = Command::from_string(&tool.cmd)?;
-            for package in packages {
-                cmd.arg(package);
-            }
-            cmd.stdout(Stdio::inherit()).pde_run()?;
-            Ok(())
-        } else {
-            for package in packages {
-                Command::from_string(&tool.cmd)?
-                    .arg(package)
-                    .stdout(Stdio::inherit())
-                    .pde_run()?;
+    fn list_profiles(config: &ProfilesConfig) -> Result<()> {
+        let mut names = config.profiles.keys().collect::<Vec<_>>();
+        names.sort();
+
+        for name in names {
+            let profile = &config.profiles[name];
+            let mut header = name.clone();
+            if !profile.extends.is_empty() {
+                header.push_str(&format!(" (extends {})", profile.extends.join(", ")));
+            }
+            if config.default_profile.as_ref() == Some(name) {
+                header.push_str(" [default]");
             }
 
-            Ok(())
+            println!("{}", header);
+            println!("   {}", config.expand_profile(name)?.modules.join(", "));
         }
+
+        Ok(())
+    }
+
+    fn print_plan(profile: &[ProfileModule]) {
+        for (idx, module) in profile.iter().enumerate() {
+            println!("{}. {}", idx + 1, module.name);
+            Self::print_hooks_plan(&module.name, "before", &module.before);
+            for step in &module.steps {
+                for line in step.plan() {
+                    println!("   {}", line);
+                }
+            }
+            Self::print_hooks_plan(&module.name, "after", &module.after);
+        }
+    }
+
+    fn print_hooks_plan(module_name: &str, kind: &str, hooks: &[String]) {
+        for hook in hooks {
+            println!("   [{}] {} hook, sh -c:", module_name, kind);
+            for line in hook.trim().lines() {
+                println!("       {}", line);
+            }
+        }
+    }
+
+    fn print_status(profile: &[ProfileModule]) {
+        let width = profile
+            .iter()
+            .flat_map(|module| &module.steps)
+            .map(|step| step.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("step".len());
+
+        let mut counts = BTreeMap::<&str, usize>::new();
+        println!("   {:<width$}  {:<7}  details", "step", "status");
+        for module in profile {
+            println!("{}", module.name);
+            for step in &module.steps {
+                let status = step
+                    .status()
+                    .unwrap_or_else(|err| StepStatus::Unknown(format!("{:#}", err)));
+                *counts.entry(status.label()).or_default() += 1;
+
+                let row = format!(
+                    "   {:<width$}  {:<7}  {}",
+                    step.name,
+                    status.label(),
+                    status.details()
+                );
+                println!("{}", row.trim_end());
+            }
+        }
+
+        let counts: Vec<String> = ["ok", "missing", "drifted", "unknown"]
+            .into_iter()
+            .filter_map(|label| Some(format!("{} {}", counts.get(label)?, label)))
+            .collect();
+        println!("{}", counts.join(", "));
     }
 
-    fn execute_script(name: &str, script: &str) -> Result<()> {
-        println!("[{}] runnning script", name);
-        Command::new("sh")
-            .arg("-c")
-            .arg(script)
-            .stdout(Stdio::inherit())
-            .pde_run()?;
+    fn print_checksums(profile: &[ProfileModule], cache: &PackageCache) -> Result<()> {
+        for module in profile {
+            for step in &module.steps {
+                let StepAction::Archive { packages, .. } = &step.action else {
+                    continue;
+                };
+
+                println!("# {}", module.step_key(step));
+                for package in packages {
+                    let digest = sha256_hex(&cache.fetch(&package.url, None, None)?);
+                    if let Some(expected) = package
+                        .sha256
+                        .as_ref()
+                        .filter(|sha| !sha.eq_ignore_ascii_case(&digest))
+                    {
+                        println!("# configured sha256 {} does not match", expected);
+                    }
+                    println!("{{ url = {:?}, sha256 = {:?} }},", package.url, digest);
+                }
+            }
+        }
+
         Ok(())
     }
 
-    fn execute_zip(name: &str, target_dir: &Path, packages: &Vec<String>) -> Result<()> {
-        println!("[{}] getting zip package", name);
-        for package_url in packages {
-            let target_name = Self::package_name_from_path(package_url)?;
-            let mut response = reqwest::blocking::get(package_url)?;
-            dbg!(&response);
-            let mut buffer = vec![];
-            response.read_to_end(&mut buffer)?;
+    fn ensure_cached(profile: &[ProfileModule], cache: &PackageCache) -> Result<()> {
+        let mut missing = vec![];
+        for step in profile.iter().flat_map(|module| &module.steps) {
+            if let StepAction::Archive { packages, .. } = &step.action {
+                for package in packages {
+                    if !cache.is_available(package)? {
+                        missing.push(format!("[{}] {}", step.name, package.url));
+                    }
+                }
+            }
+        }
 
-            let mut archive = zip::ZipArchive::new(Cursor::new(buffer))?;
-            archive.extract(target_dir.join(target_name))?
+        if !missing.is_empty() {
+            bail!(
+                "packages are not cached and cannot be downloaded offline:\n{}",
+                missing.join("\n")
+            );
         }
 
         Ok(())
     }
 
-    fn package_name_from_path(url: &str) -> Result<String> {
-        let name = url
-            .split('/')
-            .last()
-            .ok_or(anyhow!("cannot retrieve package name from url: {}", url))?;
+    fn select_modules(
+        profile: Vec<ProfileModule>,
+        cmd: &InstallCommand,
+    ) -> Result<Vec<ProfileModule>> {
+        for module_name in cmd.from.iter().chain(cmd.only.iter()) {
+            if !profile.iter().any(|module| &module.name == module_name) {
+                bail!("{} module is not part of the selected profile", module_name);
+            }
+        }
+
+        if let Some(from) = &cmd.from {
+            return Ok(profile
+                .into_iter()
+                .skip_while(|module| &module.name != from)
+                .collect());
+        }
+
+        if !cmd.only.is_empty() {
+            return Ok(profile
+                .into_iter()
+                .filter(|module| cmd.only.contains(&module.name))
+                .collect());
+        }
 
-        Ok(name.replace(".zip", ""))
+        Ok(profile)
     }
 }
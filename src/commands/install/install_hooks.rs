@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use std::{process::Command, time::Instant};
+
+use super::{install_log::InstallLog, variables::Variables};
+use crate::{
+    config::profiles::profiles_config::ProfilesConfig, modules::notification::Notification,
+    utils::command_extensions::CommandExtensions,
+};
+
+/// Profile `before` / `after` hooks of an install run and the global hooks
+/// reporting how it finished
+pub struct InstallHooks {
+    profile_names: Vec<String>,
+    before: Vec<String>,
+    after: Vec<String>,
+    on_success: Vec<String>,
+    on_failure: Vec<String>,
+    notify: bool,
+    started_at: Instant,
+}
+
+impl InstallHooks {
+    pub fn resolve(config: &ProfilesConfig, profile_names: &[String]) -> Result<Self> {
+        let variables = Variables::new(config, profile_names)?;
+        let render = |hooks: &[String]| -> Result<Vec<String>> {
+            hooks
+                .iter()
+                .map(|hook| variables.render(hook))
+                .collect::<Result<_>>()
+                .map_err(|err| anyhow!("hook: {}", err))
+        };
+
+        let mut before = vec![];
+        let mut after = vec![];
+        for profile_name in profile_names {
+            let profile = config.expand_profile(profile_name)?;
+            before.extend(render(&profile.before)?);
+            after.extend(render(&profile.after)?);
+        }
+
+        Ok(Self {
+            profile_names: profile_names.to_vec(),
+            before,
+            after,
+            on_success: render(&config.on_success)?,
+            on_failure: render(&config.on_failure)?,
+            notify: config.notify,
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn run_before(&self) -> Result<()> {
+        run_hooks("before", &self.before, &[])
+    }
+
+    pub fn run_after(&self) -> Result<()> {
+        run_hooks("after", &self.after, &[])
+    }
+
+    /// Runs `on_success` or `on_failure` hooks and sends the notification, their
+    /// failures are reported without changing the result of the run
+    pub fn finish(&self, result: &Result<()>, log: &InstallLog) {
+        let summary = log.summary();
+        let mut env = vec![
+            ("PDE_PROFILES", self.profile_names.join(" ")),
+            (
+                "PDE_RESULT",
+                if result.is_ok() { "success" } else { "failure" }.to_owned(),
+            ),
+            ("PDE_SUCCEEDED", summary.succeeded.to_string()),
+            ("PDE_SKIPPED", summary.skipped.to_string()),
+            ("PDE_FAILED", summary.failed.len().to_string()),
+            ("PDE_FAILED_STEPS", summary.failed.join(" ")),
+            (
+                "PDE_DURATION",
+                self.started_at.elapsed().as_secs().to_string(),
+            ),
+            ("PDE_LOG", log.path().display().to_string()),
+        ];
+        if let Err(err) = result {
+            env.push(("PDE_ERROR", format!("{:#}", err)));
+        }
+
+        let (name, hooks) = match result {
+            Ok(()) => ("on_success", &self.on_success),
+            Err(_) => ("on_failure", &self.on_failure),
+        };
+        if let Err(err) = run_hooks(name, hooks, &env) {
+            println!("[{}] hook failed: {:#}", name, err);
+        }
+
+        if self.notify {
+            let body = format!(
+                "{} succeeded, {} skipped, {} failed",
+                summary.succeeded,
+                summary.skipped,
+                summary.failed.len()
+            );
+            let notification = match result {
+                Ok(()) => Notification::message("Install finished"),
+                Err(_) => Notification::message("Install failed").urgency("critical"),
+            };
+            if let Err(err) = notification.body(body).send() {
+                println!("cannot send notification: {:#}", err);
+            }
+        }
+    }
+}
+
+/// Runs hook scripts in order, stopping at the first one that fails
+pub fn run_hooks(name: &str, hooks: &[String], env: &[(&str, String)]) -> Result<()> {
+    for hook in hooks {
+        println!("[{}] running hook", name);
+        Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .pde_run_prefixed(name, None, &|_| {})?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use anyhow::Result;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::profile_module::Execution;
+
+#[derive(Clone, Copy, PartialEq)]
+enum StepOutcome {
+    Succeeded,
+    Skipped,
+    Failed,
+}
+
+struct StepRecord {
+    key: String,
+    outcome: StepOutcome,
+    duration: Option<Duration>,
+}
+
+/// Step results of an install run
+pub struct RunSummary {
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: Vec<String>,
+}
+
+/// Log file of an install or uninstall run under `resource_root_dir/logs` with the
+/// output of every step, together with the step results for the end of run summary
+pub struct InstallLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    records: Mutex<Vec<StepRecord>>,
+}
+
+impl InstallLog {
+    /// Creates `logs/<command>-<timestamp in millis>.log`, never reusing the log of another run
+    pub fn create(resource_root_dir: &Path, command: &str) -> Result<Self> {
+        let dir = resource_root_dir.join("logs");
+        fs::create_dir_all(&dir)?;
+
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let mut suffix = 0;
+        let (path, file) = loop {
+            let path = match suffix {
+                0 => dir.join(format!("{}-{}.log", command, started_at)),
+                _ => dir.join(format!("{}-{}-{}.log", command, started_at, suffix)),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => suffix += 1,
+                Err(err) => return Err(err.into()),
+            }
+        };
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            records: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn skip(&self, key: &str) {
+        self.write(key, "already completed, skipping");
+        self.record(key, StepOutcome::Skipped, None);
+    }
+
+    /// Runs the step with its output going to the log and records how it finished
+    pub fn run<F>(&self, key: &str, execute: F) -> Result<()>
+    where
+        F: FnOnce(&(dyn Fn(&str) + Sync)) -> Result<Execution>,
+    {
+        self.write(key, "started");
+        let started_at = Instant::now();
+        let result = execute(&|line| self.write(key, line));
+        let duration = started_at.elapsed();
+
+        match &result {
+            Ok(Execution::Applied) => {
+                self.write(key, &format!("succeeded in {:.2?}", duration));
+                self.record(key, StepOutcome::Succeeded, Some(duration));
+            }
+            Ok(Execution::AlreadySatisfied) => {
+                self.write(key, "nothing to do, skipped");
+                self.record(key, StepOutcome::Skipped, Some(duration));
+            }
+            Err(err) => {
+                self.write(
+                    key,
+                    &format!(
+                        "failed in {:.2?}: {}",
+                        duration,
+                        format!("{:#}", err).trim_end()
+                    ),
+                );
+                self.record(key, StepOutcome::Failed, Some(duration));
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn summary(&self) -> RunSummary {
+        let records = self.records.lock().expect("install log lock poisoned");
+        let count = |outcome: StepOutcome| {
+            records
+                .iter()
+                .filter(|record| record.outcome == outcome)
+                .count()
+        };
+
+        RunSummary {
+            succeeded: count(StepOutcome::Succeeded),
+            skipped: count(StepOutcome::Skipped),
+            failed: records
+                .iter()
+                .filter(|record| record.outcome == StepOutcome::Failed)
+                .map(|record| record.key.clone())
+                .collect(),
+        }
+    }
+
+    pub fn print_summary(&self) {
+        let records = self.records.lock().expect("install log lock poisoned");
+        if records.is_empty() {
+            return;
+        }
+
+        let width = records
+            .iter()
+            .map(|record| record.key.len())
+            .max()
+            .unwrap_or_default()
+            .max("step".len());
+
+        println!();
+        println!("{:<width$}  {:<9}  duration", "step", "result");
+        for record in records.iter() {
+            let outcome = match record.outcome {
+                StepOutcome::Succeeded => "succeeded",
+                StepOutcome::Skipped => "skipped",
+                StepOutcome::Failed => "failed",
+            };
+            let duration = record
+                .duration
+                .map(|duration| format!("{:.2?}", duration))
+                .unwrap_or_default();
+            let row = format!("{:<width$}  {:<9}  {}", record.key, outcome, duration);
+            println!("{}", row.trim_end());
+        }
+        println!("log: {}", self.path.display());
+    }
+
+    fn write(&self, key: &str, line: &str) {
+        let mut file = self.file.lock().expect("install log lock poisoned");
+        // the log is best effort and never fails the install
+        let _ = writeln!(file, "[{}] {}", key, line);
+    }
+
+    fn record(&self, key: &str, outcome: StepOutcome, duration: Option<Duration>) {
+        self.records
+            .lock()
+            .expect("install log lock poisoned")
+            .push(StepRecord {
+                key: key.to_owned(),
+                outcome,
+                duration,
+            });
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct InstallState {
+    #[serde(default)]
+    steps: BTreeMap<String, StepState>,
+    last_run: Option<RunState>,
+
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StepState {
+    pub hash: String,
+    pub completed_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunState {
+    pub profiles: Vec<String>,
+    /// Steps of the run that have not finished yet, in execution order
+    pub pending: Vec<String>,
+    pub failed: Option<String>,
+}
+
+impl InstallState {
+    pub fn load(resource_root_dir: &Path) -> Result<Self> {
+        let path = resource_root_dir.join("install/state.toml");
+
+        let mut state: Self = if path.exists() {
+            toml::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            Self::default()
+        };
+        state.path = path;
+
+        Ok(state)
+    }
+
+    pub fn is_completed(&self, key: &str, hash: &str) -> bool {
+        self.steps
+            .get(key)
+            .map(|step| step.hash == hash)
+            .unwrap_or(false)
+    }
+
+    pub fn last_run(&self) -> Option<&RunState> {
+        self.last_run.as_ref()
+    }
+
+    pub fn is_pending(&self, key: &str) -> bool {
+        self.last_run
+            .as_ref()
+            .map(|run| run.pending.iter().any(|step| step == key))
+            .unwrap_or(false)
+    }
+
+    pub fn start_run(&mut self, profiles: &[String], steps: Vec<String>) -> Result<()> {
+        self.last_run = Some(RunState {
+            profiles: profiles.to_vec(),
+            pending: steps,
+            failed: None,
+        });
+
+        self.save()
+    }
+
+    pub fn skip(&mut self, key: &str) -> Result<()> {
+        self.finish(key);
+        self.save()
+    }
+
+    pub fn fail(&mut self, key: &str) -> Result<()> {
+        if let Some(run) = &mut self.last_run {
+            run.failed = Some(key.to_owned());
+        }
+
+        self.save()
+    }
+
+    pub fn complete(&mut self, key: &str, hash: &str) -> Result<()> {
+        let completed_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.steps.insert(
+            key.to_owned(),
+            StepState {
+                hash: hash.to_owned(),
+                completed_at,
+            },
+        );
+        self.finish(key);
+
+        self.save()
+    }
+
+    pub fn forget(&mut self, key: &str) -> Result<()> {
+        self.steps.remove(key);
+        self.save()
+    }
+
+    fn finish(&mut self, key: &str) {
+        if let Some(run) = &mut self.last_run {
+            run.pending.retain(|step| step != key);
+            if run.failed.as_deref() == Some(key) {
+                run.failed = None;
+            }
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, toml::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pde-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn completed_steps_are_kept_across_runs() {
+        let dir = state_dir("completed");
+        let mut state = InstallState::load(&dir).unwrap();
+        state.complete("m/s", "hash").unwrap();
+
+        let state = InstallState::load(&dir).unwrap();
+
+        assert!(state.is_completed("m/s", "hash"));
+        assert!(!state.is_completed("m/s", "changed"));
+        assert!(!state.is_completed("m/t", "hash"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn forgotten_steps_are_no_longer_completed() {
+        let dir = state_dir("forget");
+        let mut state = InstallState::load(&dir).unwrap();
+        state.complete("m/s", "hash").unwrap();
+
+        state.forget("m/s").unwrap();
+
+        assert!(!InstallState::load(&dir)
+            .unwrap()
+            .is_completed("m/s", "hash"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resumed_runs_keep_failed_and_unfinished_steps_pending() {
+        let dir = state_dir("resume");
+        let mut state = InstallState::load(&dir).unwrap();
+        let steps = ["m/a", "m/b", "m/c", "n/d"].map(String::from).to_vec();
+        state.start_run(&["desk".to_owned()], steps).unwrap();
+        state.complete("m/a", "hash").unwrap();
+        state.skip("m/b").unwrap();
+        state.fail("m/c").unwrap();
+
+        let state = InstallState::load(&dir).unwrap();
+        let run = state.last_run().unwrap();
+
+        assert_eq!(run.profiles, ["desk"]);
+        assert_eq!(run.pending, ["m/c", "n/d"]);
+        assert_eq!(run.failed.as_deref(), Some("m/c"));
+        assert!(!state.is_pending("m/a"));
+        assert!(state.is_pending("n/d"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn completing_the_failed_step_clears_the_failure() {
+        let dir = state_dir("retry");
+        let mut state = InstallState::load(&dir).unwrap();
+        state.start_run(&[], vec!["m/a".to_owned()]).unwrap();
+        state.fail("m/a").unwrap();
+
+        state.complete("m/a", "hash").unwrap();
+
+        let run = state.last_run().unwrap();
+        assert!(run.pending.is_empty());
+        assert_eq!(run.failed, None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    config::profiles::module_config::ArchivePackage,
+    utils::{archive_utils::sha256_hex, path_extensions::PathExtensions},
+};
+
+/// Content-addressed store of downloaded archives, indexed by source url
+pub struct PackageCache {
+    dir: PathBuf,
+    offline: bool,
+    /// Serializes index updates of parallel steps
+    index_lock: Mutex<()>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheIndex {
+    #[serde(default)]
+    urls: BTreeMap<String, String>,
+}
+
+impl PackageCache {
+    pub fn new(resource_root_dir: &Path, offline: bool) -> Self {
+        Self {
+            dir: resource_root_dir.join("cache"),
+            offline,
+            index_lock: Mutex::new(()),
+        }
+    }
+
+    /// Package content from a local path, the cache or the network, verified against
+    /// its configured digest
+    pub fn get(&self, package: &ArchivePackage, timeout: Option<Duration>) -> Result<Vec<u8>> {
+        self.fetch(&package.url, package.sha256.as_deref(), timeout)
+    }
+
+    /// Downloads are only cached once they match `sha256`
+    pub fn fetch(
+        &self,
+        url: &str,
+        sha256: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        if let Some(path) = Self::local_path(url) {
+            let content = fs::read(&path)
+                .map_err(|err| anyhow!("cannot read {}: {}", path.display(), err))?;
+            Self::verify(url, sha256, &content)?;
+            return Ok(content);
+        }
+
+        if let Some(content) = self.lookup(url, sha256)? {
+            return Ok(content);
+        }
+
+        if self.offline {
+            bail!("{} is not cached and cannot be downloaded offline", url);
+        }
+
+        let mut client = reqwest::blocking::Client::builder();
+        if let Some(timeout) = timeout {
+            client = client.timeout(timeout);
+        }
+        let mut response = client.build()?.get(url).send()?.error_for_status()?;
+        let mut content = vec![];
+        response.read_to_end(&mut content)?;
+
+        Self::verify(url, sha256, &content)?;
+        self.store(url, &content)?;
+
+        Ok(content)
+    }
+
+    /// Whether the package can be read without network access
+    pub fn is_available(&self, package: &ArchivePackage) -> Result<bool> {
+        if Self::local_path(&package.url).is_some() {
+            return Ok(true);
+        }
+
+        Ok(self
+            .lookup(&package.url, package.sha256.as_deref())?
+            .is_some())
+    }
+
+    fn lookup(&self, url: &str, sha256: Option<&str>) -> Result<Option<Vec<u8>>> {
+        let digest = match sha256 {
+            Some(sha256) => sha256.to_lowercase(),
+            None => match self.locked_index()?.urls.get(url) {
+                Some(digest) => digest.clone(),
+                None => return Ok(None),
+            },
+        };
+
+        let blob_path = self.blob_path(&digest);
+        if !blob_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(blob_path)?;
+        // a corrupted blob is treated as a cache miss
+        if sha256_hex(&content) != digest {
+            return Ok(None);
+        }
+
+        Ok(Some(content))
+    }
+
+    fn verify(url: &str, sha256: Option<&str>, content: &[u8]) -> Result<()> {
+        let Some(expected) = sha256 else {
+            return Ok(());
+        };
+
+        let actual = sha256_hex(content);
+        if !actual.eq_ignore_ascii_case(expected) {
+            bail!(
+                "checksum mismatch for {}\n  expected: {}\n  actual:   {}",
+                url,
+                expected,
+                actual
+            );
+        }
+
+        Ok(())
+    }
+
+    fn store(&self, url: &str, content: &[u8]) -> Result<()> {
+        let _guard = self.index_lock.lock().expect("package cache lock poisoned");
+
+        let digest = sha256_hex(content);
+        let blob_path = self.blob_path(&digest);
+        if let Some(dir) = blob_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Self::write_atomic(&blob_path, content)?;
+
+        let mut index = self.load_index()?;
+        index.urls.insert(url.to_owned(), digest);
+        Self::write_atomic(
+            &self.dir.join("index.toml"),
+            toml::to_string(&index)?.as_bytes(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Readers never see a partially written file
+    fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn locked_index(&self) -> Result<CacheIndex> {
+        let _guard = self.index_lock.lock().expect("package cache lock poisoned");
+        self.load_index()
+    }
+
+    fn load_index(&self) -> Result<CacheIndex> {
+        let index_path = self.dir.join("index.toml");
+        if !index_path.exists() {
+            return Ok(CacheIndex::default());
+        }
+
+        Ok(toml::from_str(&fs::read_to_string(index_path)?)?)
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.dir.join("archives").join(digest)
+    }
+
+    /// `file://` urls and plain paths are read in place instead of being cached, plain
+    /// paths are resolved against the profiles.toml directory by `ProfileStep::resolve`
+    fn local_path(url: &str) -> Option<PathBuf> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Some(PathBuf::from(path));
+        }
+
+        if url.contains("://") {
+            return None;
+        }
+
+        PathBuf::from(url).pde_resolve().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, net::TcpListener, thread};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pde-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Local stand-in for an archive server answering every request with `body`
+    fn serve(body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tool.tar", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(body);
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn get_verifies_the_configured_digest() {
+        let dir = scratch_dir("digest");
+        let path = dir.join("tool.tar");
+        fs::write(&path, "archive").unwrap();
+        let cache = PackageCache::new(&dir, false);
+        let package = |sha256: &str| ArchivePackage {
+            url: format!("file://{}", path.display()),
+            sha256: Some(sha256.to_owned()),
+        };
+
+        let content = cache.get(&package(&sha256_hex(b"archive").to_uppercase()), None);
+        let err = cache
+            .get(&package(&sha256_hex(b"other")), None)
+            .unwrap_err();
+
+        assert_eq!(content.unwrap(), b"archive");
+        assert!(err.to_string().starts_with("checksum mismatch for file://"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn downloads_are_cached_for_offline_installs() {
+        let dir = scratch_dir("offline");
+        let url = serve(b"archive");
+
+        let content = PackageCache::new(&dir, false).fetch(&url, None, None);
+
+        let offline = PackageCache::new(&dir, true);
+        assert_eq!(content.unwrap(), b"archive");
+        assert_eq!(offline.fetch(&url, None, None).unwrap(), b"archive");
+        let package = ArchivePackage {
+            url: url.clone(),
+            sha256: Some(sha256_hex(b"archive")),
+        };
+        assert!(offline.is_available(&package).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn offline_fetch_fails_for_uncached_urls() {
+        let dir = scratch_dir("uncached");
+
+        let err = PackageCache::new(&dir, true)
+            .fetch("https://example.com/tool.tar", None, None)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "https://example.com/tool.tar is not cached and cannot be downloaded offline"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn mismatching_downloads_are_not_cached() {
+        let dir = scratch_dir("mismatch");
+        let url = serve(b"tampered");
+        let package = ArchivePackage {
+            url: url.clone(),
+            sha256: Some(sha256_hex(b"archive")),
+        };
+
+        let err = PackageCache::new(&dir, false)
+            .get(&package, None)
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with("checksum mismatch for http://"));
+        assert!(PackageCache::new(&dir, true)
+            .fetch(&url, None, None)
+            .is_err());
+        assert!(!dir
+            .join("cache/archives")
+            .join(sha256_hex(b"tampered"))
+            .exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
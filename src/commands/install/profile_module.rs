@@ -0,0 +1,1875 @@
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{package_cache::PackageCache, variables::Variables, written_files::WrittenFiles};
+use crate::{
+    config::profiles::{
+        module_config::{
+            ArchivePackage, Condition, ConflictPolicy, ModuleConfig, ModuleStep, StepKind,
+            StepPolicy,
+        },
+        profiles_config::ProfilesConfig,
+        tools_config::ToolConfig,
+    },
+    modules::{git::Git, host::Host, package_manager::PackageManager, systemd::Systemd},
+    utils::{
+        archive_utils::{archive_stem, extract_archive, sha256_hex, ArchiveFormat},
+        command_extensions::CommandExtensions,
+        fs_utils::{copy_recursive, is_symlink_to, path_exists, remove_path, same_content},
+        path_extensions::PathExtensions,
+    },
+};
+
+#[derive(Debug)]
+pub struct ProfileModule {
+    pub name: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    pub steps: Vec<ProfileStep>,
+}
+
+#[derive(Debug)]
+pub struct ProfileStep {
+    pub name: String,
+    /// Script that exits 0 when the step is already satisfied
+    pub check: Option<String>,
+    pub parallel: bool,
+    pub policy: StepPolicy,
+    pub action: StepAction,
+}
+
+#[derive(Debug)]
+pub enum StepAction {
+    Tool {
+        config: ToolConfig,
+        packages: Vec<String>,
+    },
+    Git {
+        url: String,
+        dest: PathBuf,
+        rev: Option<String>,
+        branch: Option<String>,
+        depth: Option<u32>,
+    },
+    Files {
+        mode: FileMode,
+        mappings: Vec<FileMapping>,
+        on_conflict: ConflictPolicy,
+    },
+    Template {
+        source: PathBuf,
+        dest: PathBuf,
+        content: String,
+        on_conflict: ConflictPolicy,
+    },
+    Script {
+        script: String,
+        undo: Option<String>,
+    },
+    Archive {
+        target_dir: PathBuf,
+        packages: Vec<ArchivePackage>,
+        strip_components: usize,
+        target_name: Option<String>,
+    },
+    Service {
+        unit: String,
+        unit_file: Option<FileMapping>,
+        enable: bool,
+        start: bool,
+        restart_on_change: bool,
+    },
+}
+
+/// How a step that did not fail was executed or undone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Execution {
+    Applied,
+    /// Nothing was done because the `check` passed, all packages were installed or
+    /// there was nothing to undo
+    AlreadySatisfied,
+}
+
+/// Whether the host still matches a step, without changing anything
+#[derive(Debug)]
+pub enum StepStatus {
+    Ok,
+    Missing(String),
+    Drifted(String),
+    /// The step cannot be evaluated without running it
+    Unknown(String),
+}
+
+impl StepStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            StepStatus::Ok => "ok",
+            StepStatus::Missing(_) => "missing",
+            StepStatus::Drifted(_) => "drifted",
+            StepStatus::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn details(&self) -> &str {
+        match self {
+            StepStatus::Ok => "",
+            StepStatus::Missing(details)
+            | StepStatus::Drifted(details)
+            | StepStatus::Unknown(details) => details,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileMode {
+    Link,
+    Copy,
+}
+
+#[derive(Debug)]
+pub struct FileMapping {
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+impl ProfileModule {
+    /// Resolves the modules of the given profiles into executable steps
+    pub fn resolve(config: &ProfilesConfig, profile_names: &[String]) -> Result<Vec<Self>> {
+        let variables = Variables::new(config, profile_names)?;
+        Self::from_configs(config, config.resolve_modules(profile_names)?, &variables)
+    }
+
+    pub fn from_configs(
+        config: &ProfilesConfig,
+        modules: Vec<&ModuleConfig>,
+        variables: &Variables,
+    ) -> Result<Vec<Self>> {
+        let mut resolved = vec![];
+        for module in modules {
+            if let Some(name) = module.duplicate_steps().first() {
+                bail!(
+                    "{} module has more than one {} step, step names have to be unique",
+                    module.name,
+                    name
+                );
+            }
+            if !is_condition_met(module.when.as_ref()) {
+                continue;
+            }
+
+            let mut steps = vec![];
+            for step in module.steps.iter().cloned() {
+                if !is_condition_met(step.common.when.as_ref()) {
+                    continue;
+                }
+
+                let key = format!("{}/{}", module.name, step.common.name);
+                let mut step = ProfileStep::resolve(config, variables, step)
+                    .map_err(|err| anyhow!("{} step: {}", key, err))?;
+                step.parallel |= module.parallel;
+                steps.push(step);
+            }
+
+            let render = |hooks: &[String]| -> Result<Vec<String>> {
+                hooks
+                    .iter()
+                    .map(|hook| variables.render(hook))
+                    .collect::<Result<_>>()
+                    .map_err(|err| anyhow!("{} hook: {}", module.name, err))
+            };
+
+            resolved.push(Self {
+                name: module.name.clone(),
+                before: render(&module.before)?,
+                after: render(&module.after)?,
+                steps,
+            });
+        }
+
+        Ok(resolved)
+    }
+
+    /// Identifies the step in the install state
+    pub fn step_key(&self, step: &ProfileStep) -> String {
+        format!("{}/{}", self.name, step.name)
+    }
+}
+
+impl ProfileStep {
+    fn resolve(config: &ProfilesConfig, variables: &Variables, step: ModuleStep) -> Result<Self> {
+        let ModuleStep { common, kind } = variables.interpolate_step(step)?;
+        let mut policy = common.policy;
+        let action = match kind {
+            StepKind::Tool { tool, packages } => {
+                let tool_config = config
+                    .tools
+                    .get(&tool)
+                    .cloned()
+                    .ok_or(anyhow!("{} tool not found", &tool))?;
+                policy = policy.or_tool(&tool_config);
+
+                StepAction::Tool {
+                    config: tool_config,
+                    packages,
+                }
+            }
+            StepKind::Packages {
+                packages,
+                manager,
+                overrides,
+            } => {
+                let manager = match manager {
+                    Some(manager) => manager,
+                    None => PackageManager::detect()
+                        .ok_or(anyhow!("no supported package manager found on the host"))?,
+                };
+
+                StepAction::Tool {
+                    config: manager.tool_config(),
+                    packages: manager.package_names(&packages, &overrides),
+                }
+            }
+            StepKind::Git {
+                git,
+                dest,
+                rev,
+                branch,
+                depth,
+            } => StepAction::Git {
+                url: git,
+                dest,
+                rev,
+                branch,
+                depth,
+            },
+            StepKind::Link { link, on_conflict } => StepAction::Files {
+                mode: FileMode::Link,
+                mappings: Self::resolve_mappings(config, link)?,
+                on_conflict,
+            },
+            StepKind::Copy { copy, on_conflict } => StepAction::Files {
+                mode: FileMode::Copy,
+                mappings: Self::resolve_mappings(config, copy)?,
+                on_conflict,
+            },
+            StepKind::Template {
+                template,
+                dest,
+                on_conflict,
+            } => {
+                let source = config.root_dir.join(template);
+                let content = fs::read_to_string(&source)
+                    .map_err(|err| anyhow!("cannot read {}: {}", source.display(), err))
+                    .and_then(|template| variables.render(&template))?;
+
+                StepAction::Template {
+                    source,
+                    dest,
+                    content,
+                    on_conflict,
+                }
+            }
+            StepKind::Script { script, undo } => StepAction::Script { script, undo },
+            StepKind::Archive {
+                extract_to,
+                packages,
+                strip_components,
+                target_name,
+            } => {
+                if target_name.is_some() && packages.len() > 1 {
+                    bail!("target_name requires a single package");
+                }
+
+                // plain paths are relative to the profiles.toml directory like other sources
+                let packages = packages
+                    .into_iter()
+                    .map(|package| match package.url.contains("://") {
+                        true => Ok(package),
+                        false => Ok(ArchivePackage {
+                            url: config
+                                .root_dir
+                                .join(PathBuf::from(&package.url).pde_resolve()?)
+                                .display()
+                                .to_string(),
+                            ..package
+                        }),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                StepAction::Archive {
+                    target_dir: extract_to,
+                    packages,
+                    strip_components,
+                    target_name,
+                }
+            }
+            StepKind::Service {
+                service,
+                unit_file,
+                enable,
+                start,
+                restart_on_change,
+            } => {
+                let unit_file = match unit_file {
+                    Some(unit_file) => {
+                        let source = config.root_dir.join(unit_file);
+                        let file_name = source
+                            .file_name()
+                            .ok_or(anyhow!("{} is not a unit file", source.display()))?;
+                        let target = Systemd::user_unit_dir()?.join(file_name);
+                        Some(FileMapping { source, target })
+                    }
+                    None => None,
+                };
+
+                StepAction::Service {
+                    unit: service,
+                    unit_file,
+                    enable: enable.unwrap_or(true),
+                    start: start.unwrap_or(true),
+                    restart_on_change: restart_on_change.unwrap_or(true),
+                }
+            }
+        };
+
+        Ok(Self {
+            name: common.name,
+            check: common.check,
+            parallel: common.parallel,
+            policy,
+            action,
+        })
+    }
+
+    fn resolve_mappings(
+        config: &ProfilesConfig,
+        mappings: BTreeMap<String, String>,
+    ) -> Result<Vec<FileMapping>> {
+        mappings
+            .into_iter()
+            .map(|(source, target)| {
+                Ok(FileMapping {
+                    source: config.root_dir.join(source),
+                    target: PathBuf::from(target).pde_resolve()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Git steps following a branch are never skipped as completed, so that every
+    /// install fast-forwards them
+    pub fn tracks_upstream(&self) -> bool {
+        matches!(self.action, StepAction::Git { rev: None, .. })
+    }
+
+    /// Digest of everything that affects the step outcome, used to detect changed steps
+    pub fn fingerprint(&self) -> String {
+        let inputs: Vec<String> = match &self.action {
+            StepAction::Tool { config, packages } => [
+                vec![
+                    "tool".to_owned(),
+                    config.cmd.clone(),
+                    config.batching.to_string(),
+                ],
+                packages.clone(),
+            ]
+            .concat(),
+            StepAction::Git {
+                url,
+                dest,
+                rev,
+                branch,
+                depth,
+            } => vec![
+                "git".to_owned(),
+                url.clone(),
+                dest.display().to_string(),
+                format!("{:?}", rev),
+                format!("{:?}", branch),
+                format!("{:?}", depth),
+            ],
+            StepAction::Files {
+                mode,
+                mappings,
+                on_conflict,
+            } => {
+                let mut inputs = vec![format!("{:?}", mode), format!("{:?}", on_conflict)];
+                for FileMapping { source, target } in mappings {
+                    inputs.push(format!("{}={}", source.display(), target.display()));
+                    // copies go stale when the source changes, links follow it
+                    if *mode == FileMode::Copy {
+                        Self::content_inputs(source, &mut inputs);
+                    }
+                }
+                inputs
+            }
+            StepAction::Template { dest, content, .. } => vec![
+                "template".to_owned(),
+                dest.display().to_string(),
+                content.clone(),
+            ],
+            StepAction::Script { script, .. } => vec!["script".to_owned(), script.clone()],
+            StepAction::Archive {
+                target_dir,
+                packages,
+                strip_components,
+                target_name,
+            } => {
+                // extra options are only hashed when set so plain zip steps keep their fingerprint
+                let mut inputs = vec!["zip".to_owned(), target_dir.display().to_string()];
+                for package in packages {
+                    inputs.push(package.url.clone());
+                    if let Some(sha256) = &package.sha256 {
+                        inputs.push(format!("sha256={}", sha256));
+                    }
+                }
+                if *strip_components > 0 {
+                    inputs.push(format!("strip_components={}", strip_components));
+                }
+                if let Some(target_name) = target_name {
+                    inputs.push(format!("target_name={}", target_name));
+                }
+                inputs
+            }
+            StepAction::Service {
+                unit,
+                unit_file,
+                enable,
+                start,
+                restart_on_change,
+            } => {
+                let mut inputs = vec![
+                    "service".to_owned(),
+                    unit.clone(),
+                    format!("enable={} start={}", enable, start),
+                    format!("restart_on_change={}", restart_on_change),
+                ];
+                if let Some(FileMapping { source, target }) = unit_file {
+                    // the unit file content is hashed so that edits re-run the step
+                    inputs.push(target.display().to_string());
+                    inputs.push(fs::read_to_string(source).unwrap_or_default());
+                }
+                inputs
+            }
+        };
+
+        let mut hasher = Sha256::new();
+        for input in inputs {
+            hasher.update(input.as_bytes());
+            hasher.update([0]);
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Digests of the file or of every file in the directory, unreadable entries are left out
+    fn content_inputs(path: &Path, inputs: &mut Vec<String>) {
+        if path.is_dir() {
+            let Ok(entries) = fs::read_dir(path) else {
+                return;
+            };
+            let mut entries: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+            entries.sort();
+            for entry in entries {
+                Self::content_inputs(&entry, inputs);
+            }
+        } else if let Ok(content) = fs::read(path) {
+            inputs.push(format!("{}:{}", path.display(), sha256_hex(&content)));
+        }
+    }
+
+    /// Runs the step, passing output lines of the commands it runs to `output`
+    pub fn execute(
+        &self,
+        cache: &PackageCache,
+        files: &WrittenFiles,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<Execution> {
+        if let Some(check) = &self.check {
+            if Self::is_satisfied(check)? {
+                report(&self.name, output, "already satisfied, skipping");
+                return Ok(Execution::AlreadySatisfied);
+            }
+        }
+
+        let attempts = self.policy.retries.unwrap_or(0) + 1;
+        let retry_delay = Duration::from_secs(self.policy.retry_delay.unwrap_or(5));
+        let mut attempt = 1;
+        loop {
+            let err = match self.execute_action(cache, files, output) {
+                Ok(execution) => return Ok(execution),
+                Err(err) if attempt == attempts && attempts > 1 => {
+                    return Err(err.context(format!("failed after {} attempts", attempts)))
+                }
+                Err(err) if attempt == attempts => return Err(err),
+                Err(err) => err,
+            };
+
+            let message = format!(
+                "attempt {}/{} failed, retrying in {}s: {}",
+                attempt,
+                attempts,
+                retry_delay.as_secs(),
+                err.to_string().lines().last().unwrap_or_default()
+            );
+            report(&self.name, output, &message);
+            thread::sleep(retry_delay);
+            attempt += 1;
+        }
+    }
+
+    fn execute_action(
+        &self,
+        cache: &PackageCache,
+        files: &WrittenFiles,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<Execution> {
+        let timeout = self.policy.timeout.map(Duration::from_secs);
+        match &self.action {
+            StepAction::Tool { config, packages } => {
+                return Self::execute_tool(&self.name, config, packages, timeout, output)
+            }
+            StepAction::Git {
+                url,
+                dest,
+                rev,
+                branch,
+                depth,
+            } => Self::execute_git(&self.name, url, dest, rev, branch, *depth, output)?,
+            StepAction::Files {
+                mode,
+                mappings,
+                on_conflict,
+            } => Self::execute_files(&self.name, *mode, mappings, *on_conflict, files, output)?,
+            StepAction::Template {
+                source,
+                dest,
+                content,
+                on_conflict,
+            } => Self::execute_template(
+                &self.name,
+                source,
+                dest,
+                content,
+                *on_conflict,
+                files,
+                output,
+            )?,
+            StepAction::Script { script, .. } => {
+                Self::execute_script(&self.name, script, timeout, output)?
+            }
+            StepAction::Archive {
+                target_dir,
+                packages,
+                strip_components,
+                target_name,
+            } => {
+                report(&self.name, output, "getting archive package");
+                Self::execute_archive(
+                    cache,
+                    target_dir,
+                    packages,
+                    *strip_components,
+                    target_name,
+                    timeout,
+                )?
+            }
+            StepAction::Service {
+                unit,
+                unit_file,
+                enable,
+                start,
+                restart_on_change,
+            } => Self::execute_service(
+                &self.name,
+                unit,
+                unit_file.as_ref(),
+                *enable,
+                *start,
+                *restart_on_change,
+                output,
+            )?,
+        }
+
+        Ok(Execution::Applied)
+    }
+
+    /// Reverts what `execute` did, as far as the step configuration allows
+    pub fn undo(&self, files: &WrittenFiles, output: &(dyn Fn(&str) + Sync)) -> Result<Execution> {
+        match &self.action {
+            StepAction::Tool { config, packages } => {
+                return Self::undo_tool(&self.name, config, packages, output)
+            }
+            StepAction::Git { url, dest, .. } => Self::undo_git(&self.name, url, dest, output)?,
+            StepAction::Files { mode, mappings, .. } => {
+                Self::undo_files(&self.name, *mode, mappings, files, output)?
+            }
+            StepAction::Template { dest, content, .. } => {
+                let is_own = files.is_written(dest)?
+                    || fs::read_to_string(dest).is_ok_and(|current| current == *content);
+                if is_own {
+                    Self::remove_written(&self.name, dest, files, output)?;
+                } else if path_exists(dest) {
+                    report(
+                        &self.name,
+                        output,
+                        &format!("{} was changed, keeping it", dest.display()),
+                    );
+                }
+            }
+            StepAction::Script { undo, .. } => match undo {
+                Some(undo) => Self::execute_script(&self.name, undo, None, output)?,
+                None => {
+                    report(&self.name, output, "no undo script defined, skipping");
+                    return Ok(Execution::AlreadySatisfied);
+                }
+            },
+            StepAction::Archive {
+                target_dir,
+                packages,
+                target_name,
+                ..
+            } => Self::undo_archive(&self.name, target_dir, packages, target_name, output)?,
+            StepAction::Service {
+                unit,
+                unit_file,
+                enable,
+                start,
+                ..
+            } => Self::undo_service(
+                &self.name,
+                unit,
+                unit_file.as_ref(),
+                *enable,
+                *start,
+                output,
+            )?,
+        }
+
+        Ok(Execution::Applied)
+    }
+
+    /// Human-readable description of what `execute` would do, one line per action
+    pub fn plan(&self) -> Vec<String> {
+        let name = &self.name;
+        let mut lines = vec![];
+        if self.parallel {
+            lines.push(format!("[{}] runs in parallel with adjacent steps", name));
+        }
+        let mut policy = vec![];
+        if let Some(retries) = self.policy.retries {
+            let delay = self.policy.retry_delay.unwrap_or(5);
+            policy.push(format!("{} retries {}s apart", retries, delay));
+        }
+        if let Some(timeout) = self.policy.timeout {
+            policy.push(format!("{}s timeout", timeout));
+        }
+        if self.policy.continue_on_error {
+            policy.push("continues on error".to_owned());
+        }
+        if !policy.is_empty() {
+            lines.push(format!("[{}] {}", name, policy.join(", ")));
+        }
+        if let Some(check) = &self.check {
+            lines.push(format!("[{}] unless `{}` succeeds:", name, check.trim()));
+        }
+
+        match &self.action {
+            StepAction::Tool { config, packages } => {
+                if config.batching {
+                    lines.push(format!("[{}] {} {}", name, config.cmd, packages.join(" ")));
+                } else {
+                    lines.extend(
+                        packages
+                            .iter()
+                            .map(|package| format!("[{}] {} {}", name, config.cmd, package)),
+                    );
+                }
+                if let Some(check_cmd) = &config.check_cmd {
+                    lines.push(format!(
+                        "[{}] skipping packages for which `{} <package>` succeeds",
+                        name, check_cmd
+                    ));
+                }
+            }
+            StepAction::Git {
+                url,
+                dest,
+                rev,
+                branch,
+                ..
+            } => {
+                let target = match (rev, branch) {
+                    (Some(rev), _) => format!(" at {}", rev),
+                    (None, Some(branch)) => format!(" on {}", branch),
+                    (None, None) => String::new(),
+                };
+                lines.push(format!(
+                    "[{}] clone or update {} -> {}{}",
+                    name,
+                    url,
+                    dest.display(),
+                    target
+                ));
+            }
+            StepAction::Files {
+                mode,
+                mappings,
+                on_conflict,
+            } => lines.extend(mappings.iter().map(|mapping| {
+                format!(
+                    "[{}] {} {} -> {} (on conflict: {})",
+                    name,
+                    format!("{:?}", mode).to_lowercase(),
+                    mapping.source.display(),
+                    mapping.target.display(),
+                    format!("{:?}", on_conflict).to_lowercase()
+                )
+            })),
+            StepAction::Template {
+                source,
+                dest,
+                on_conflict,
+                ..
+            } => lines.push(format!(
+                "[{}] render {} -> {} (on conflict: {})",
+                name,
+                source.display(),
+                dest.display(),
+                format!("{:?}", on_conflict).to_lowercase()
+            )),
+            StepAction::Script { script, .. } => {
+                lines.push(format!("[{}] sh -c:", name));
+                lines.extend(script.trim().lines().map(|line| format!("    {}", line)));
+            }
+            StepAction::Archive {
+                target_dir,
+                packages,
+                strip_components,
+                target_name,
+            } => lines.extend(packages.iter().map(|package| {
+                let package_url = &package.url;
+                let target = Self::package_dir(target_dir, package_url, target_name)
+                    .map(|package_dir| package_dir.display().to_string())
+                    .unwrap_or_else(|err| err.to_string());
+                if *strip_components > 0 {
+                    format!(
+                        "[{}] {} -> {} (stripping {} components)",
+                        name, package_url, target, strip_components
+                    )
+                } else {
+                    format!("[{}] {} -> {}", name, package_url, target)
+                }
+            })),
+            StepAction::Service {
+                unit,
+                unit_file,
+                enable,
+                start,
+                restart_on_change,
+            } => {
+                if let Some(FileMapping { source, target }) = unit_file {
+                    lines.push(format!(
+                        "[{}] install {} -> {} and reload systemd",
+                        name,
+                        source.display(),
+                        target.display()
+                    ));
+                    if *restart_on_change {
+                        lines.push(format!(
+                            "[{}] restart {} if running and the unit file changed",
+                            name, unit
+                        ));
+                    }
+                }
+                match (enable, start) {
+                    (true, true) => lines.push(format!("[{}] enable and start {}", name, unit)),
+                    (true, false) => lines.push(format!("[{}] enable {}", name, unit)),
+                    (false, true) => lines.push(format!("[{}] start {}", name, unit)),
+                    (false, false) => {}
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// Evaluates the step `check`, or the state its action would produce when it has none
+    pub fn status(&self) -> Result<StepStatus> {
+        if let Some(check) = &self.check {
+            return Ok(match Self::is_satisfied(check)? {
+                true => StepStatus::Ok,
+                false => StepStatus::Missing(format!("`{}` fails", check.trim())),
+            });
+        }
+
+        match &self.action {
+            StepAction::Tool { config, packages } => {
+                let Some(installed) = Self::installed_packages(config, packages)? else {
+                    return Ok(StepStatus::Unknown("tool has no check_cmd".to_owned()));
+                };
+
+                let missing: Vec<&str> = packages
+                    .iter()
+                    .filter(|package| !installed.contains(package))
+                    .map(String::as_str)
+                    .collect();
+                if missing.is_empty() {
+                    Ok(StepStatus::Ok)
+                } else {
+                    Ok(StepStatus::Missing(format!(
+                        "not installed: {}",
+                        missing.join(", ")
+                    )))
+                }
+            }
+            StepAction::Git {
+                dest, rev, branch, ..
+            } => Self::git_status(dest, rev, branch),
+            StepAction::Files { mode, mappings, .. } => Self::files_status(*mode, mappings),
+            StepAction::Template { dest, content, .. } => Ok(match fs::read_to_string(dest) {
+                Ok(current) if current == *content => StepStatus::Ok,
+                Ok(_) => StepStatus::Drifted(format!("{} was changed", dest.display())),
+                Err(_) => StepStatus::Missing(format!("{} does not exist", dest.display())),
+            }),
+            StepAction::Script { .. } => Ok(StepStatus::Unknown(
+                "script has no check defined".to_owned(),
+            )),
+            StepAction::Archive {
+                target_dir,
+                packages,
+                target_name,
+                ..
+            } => {
+                let mut missing = vec![];
+                for package in packages {
+                    let package_dir = Self::package_dir(target_dir, &package.url, target_name)?;
+                    if !package_dir.exists() {
+                        missing.push(package_dir.display().to_string());
+                    }
+                }
+
+                if missing.is_empty() {
+                    Ok(StepStatus::Ok)
+                } else {
+                    Ok(StepStatus::Missing(format!(
+                        "not extracted: {}",
+                        missing.join(", ")
+                    )))
+                }
+            }
+            StepAction::Service {
+                unit,
+                unit_file,
+                enable,
+                start,
+                ..
+            } => Self::service_status(unit, unit_file.as_ref(), *enable, *start),
+        }
+    }
+
+    fn service_status(
+        unit: &str,
+        unit_file: Option<&FileMapping>,
+        enable: bool,
+        start: bool,
+    ) -> Result<StepStatus> {
+        if let Some(FileMapping { source, target }) = unit_file {
+            if !path_exists(target) {
+                return Ok(StepStatus::Missing(format!(
+                    "{} does not exist",
+                    target.display()
+                )));
+            }
+            if !Self::is_mapping_applied(FileMode::Copy, source, target)? {
+                return Ok(StepStatus::Drifted(format!(
+                    "{} was changed",
+                    target.display()
+                )));
+            }
+        }
+
+        let mut missing = vec![];
+        if enable && !Systemd::is_enabled(unit)? {
+            missing.push("not enabled");
+        }
+        if start && !Systemd::is_active(unit)? {
+            missing.push("not running");
+        }
+
+        if missing.is_empty() {
+            Ok(StepStatus::Ok)
+        } else {
+            Ok(StepStatus::Missing(format!(
+                "{} {}",
+                unit,
+                missing.join(", ")
+            )))
+        }
+    }
+
+    fn git_status(
+        dest: &Path,
+        rev: &Option<String>,
+        branch: &Option<String>,
+    ) -> Result<StepStatus> {
+        if !Git::is_repo(dest) {
+            return Ok(StepStatus::Missing(format!(
+                "{} is not cloned",
+                dest.display()
+            )));
+        }
+
+        let head = Git::head(dest)?;
+        if let Some(rev) = rev {
+            // a rev that was never fetched cannot be checked out either
+            if Git::has_commit(dest, rev)? && Git::rev_parse(dest, rev)? == head {
+                return Ok(StepStatus::Ok);
+            }
+
+            return Ok(StepStatus::Drifted(format!(
+                "at {}, expected {}",
+                Git::short(&head),
+                rev
+            )));
+        }
+
+        if let Some(branch) = branch {
+            let current = Git::current_branch(dest)?;
+            if current.as_ref() != Some(branch) {
+                return Ok(StepStatus::Drifted(format!(
+                    "on {}, expected {}",
+                    current.as_deref().unwrap_or("detached HEAD"),
+                    branch
+                )));
+            }
+        }
+
+        Ok(StepStatus::Ok)
+    }
+
+    fn files_status(mode: FileMode, mappings: &[FileMapping]) -> Result<StepStatus> {
+        let mut missing = vec![];
+        let mut changed = vec![];
+        for FileMapping { source, target } in mappings {
+            if Self::is_mapping_applied(mode, source, target)? {
+                continue;
+            }
+
+            if path_exists(target) {
+                changed.push(target.display().to_string());
+            } else {
+                missing.push(target.display().to_string());
+            }
+        }
+
+        if !changed.is_empty() {
+            Ok(StepStatus::Drifted(format!(
+                "changed: {}",
+                changed.join(", ")
+            )))
+        } else if !missing.is_empty() {
+            Ok(StepStatus::Missing(format!(
+                "missing: {}",
+                missing.join(", ")
+            )))
+        } else {
+            Ok(StepStatus::Ok)
+        }
+    }
+
+    fn is_satisfied(check: &str) -> Result<bool> {
+        Command::new("sh").arg("-c").arg(check).pde_succeeds()
+    }
+
+    /// Packages reported as installed by the tool `check_cmd`, `None` if it has none
+    fn installed_packages(tool: &ToolConfig, packages: &[String]) -> Result<Option<Vec<String>>> {
+        let Some(check_cmd) = &tool.check_cmd else {
+            return Ok(None);
+        };
+
+        let mut installed = vec![];
+        for package in packages {
+            if Command::from_string(check_cmd)?
+                .arg(package)
+                .pde_succeeds()?
+            {
+                installed.push(package.clone());
+            }
+        }
+
+        Ok(Some(installed))
+    }
+
+    fn execute_tool(
+        name: &str,
+        tool: &ToolConfig,
+        packages: &[String],
+        timeout: Option<Duration>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<Execution> {
+        let installed = Self::installed_packages(tool, packages)?.unwrap_or_default();
+        for package in &installed {
+            report(name, output, &format!("{} already installed", package));
+        }
+
+        let packages: Vec<&String> = packages
+            .iter()
+            .filter(|package| !installed.contains(package))
+            .collect();
+        if packages.is_empty() {
+            report(name, output, "all packages already installed, skipping");
+            return Ok(Execution::AlreadySatisfied);
+        }
+
+        report(name, output, &format!("executing tool: {}", &tool.cmd));
+        if tool.batching {
+            let mut cmd = Command::from_string(&tool.cmd)?;
+            for package in packages {
+                cmd.arg(package);
+            }
+            cmd.pde_run_prefixed(name, timeout, output)?;
+        } else {
+            for package in packages {
+                Command::from_string(&tool.cmd)?
+                    .arg(package)
+                    .pde_run_prefixed(name, timeout, output)?;
+            }
+        }
+
+        Ok(Execution::Applied)
+    }
+
+    fn undo_tool(
+        name: &str,
+        tool: &ToolConfig,
+        packages: &[String],
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<Execution> {
+        let Some(uninstall_cmd) = &tool.uninstall_cmd else {
+            report(name, output, "tool has no uninstall_cmd defined, skipping");
+            return Ok(Execution::AlreadySatisfied);
+        };
+
+        let packages =
+            Self::installed_packages(tool, packages)?.unwrap_or_else(|| packages.to_vec());
+        if packages.is_empty() {
+            report(name, output, "no packages installed, skipping");
+            return Ok(Execution::AlreadySatisfied);
+        }
+
+        report(name, output, &format!("executing tool: {}", uninstall_cmd));
+        if tool.batching {
+            Command::from_string(uninstall_cmd)?
+                .args(packages)
+                .pde_run_prefixed(name, None, output)?;
+        } else {
+            for package in packages {
+                Command::from_string(uninstall_cmd)?
+                    .arg(package)
+                    .pde_run_prefixed(name, None, output)?;
+            }
+        }
+
+        Ok(Execution::Applied)
+    }
+
+    fn execute_git(
+        name: &str,
+        url: &str,
+        dest: &Path,
+        rev: &Option<String>,
+        branch: &Option<String>,
+        depth: Option<u32>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        if !Git::is_repo(dest) {
+            report(name, output, &format!("cloning {}", url));
+            Git::clone(url, dest, branch.as_deref(), depth, output)?;
+            if let Some(rev) = rev {
+                Self::checkout_rev(dest, rev, depth, output)?;
+            }
+            report(
+                name,
+                output,
+                &format!(
+                    "cloned {} at {}",
+                    dest.display(),
+                    Git::short(&Git::head(dest)?)
+                ),
+            );
+            return Ok(());
+        }
+
+        let before = Git::head(dest)?;
+        if let Some(rev) = rev {
+            Self::checkout_rev(dest, rev, depth, output)?;
+        } else {
+            Git::fetch(dest, None, depth, output)?;
+            if let Some(branch) = branch {
+                Git::checkout(dest, branch, output)?;
+            }
+
+            match Git::current_branch(dest)? {
+                Some(branch) => Git::fast_forward(dest, &branch, output)?,
+                None => report(name, output, "HEAD is detached, not updating"),
+            }
+        }
+
+        let after = Git::head(dest)?;
+        if before == after {
+            report(
+                name,
+                output,
+                &format!("already up to date at {}", Git::short(&after)),
+            );
+        } else {
+            report(
+                name,
+                output,
+                &format!("updated {} -> {}", Git::short(&before), Git::short(&after)),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Removes the checkout unless it holds work that is not on the remote
+    fn undo_git(name: &str, url: &str, dest: &Path, output: &(dyn Fn(&str) + Sync)) -> Result<()> {
+        if !Git::is_repo(dest) {
+            return Ok(());
+        }
+
+        let keep_reason = if Git::remote_url(dest)?.as_deref() != Some(url) {
+            Some(format!("is not a clone of {}", url))
+        } else if Git::has_changes(dest)? {
+            Some("has uncommitted changes".to_owned())
+        } else if Git::has_unpushed_commits(dest)? {
+            Some("has commits that are not on the remote".to_owned())
+        } else {
+            None
+        };
+
+        if let Some(reason) = keep_reason {
+            report(
+                name,
+                output,
+                &format!("{} {}, keeping it", dest.display(), reason),
+            );
+            return Ok(());
+        }
+
+        report(name, output, &format!("removing {}", dest.display()));
+        fs::remove_dir_all(dest)?;
+        Ok(())
+    }
+
+    fn checkout_rev(
+        repo: &Path,
+        rev: &str,
+        depth: Option<u32>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        Git::fetch(repo, None, depth, output)?;
+        if Git::has_commit(repo, rev)? {
+            Git::checkout_detached(repo, rev, output)
+        } else {
+            // commits outside of fetched refs have to be requested explicitly
+            Git::fetch(repo, Some(rev), depth, output)?;
+            Git::checkout_detached(repo, "FETCH_HEAD", output)
+        }
+    }
+
+    fn execute_files(
+        name: &str,
+        mode: FileMode,
+        mappings: &[FileMapping],
+        on_conflict: ConflictPolicy,
+        files: &WrittenFiles,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        for FileMapping { source, target } in mappings {
+            if !path_exists(source) {
+                bail!("{} does not exist", source.display());
+            }
+
+            if Self::is_mapping_applied(mode, source, target)? {
+                report(name, output, &format!("{} unchanged", target.display()));
+                continue;
+            }
+
+            let own_copy = mode == FileMode::Copy && files.is_written(target)?;
+            if !Self::clear_target(name, target, on_conflict, own_copy, output)? {
+                continue;
+            }
+
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            match mode {
+                FileMode::Link => {
+                    symlink(source, target)?;
+                    report(
+                        name,
+                        output,
+                        &format!("linked {} -> {}", target.display(), source.display()),
+                    );
+                }
+                FileMode::Copy => {
+                    copy_recursive(source, target)?;
+                    files.record(target)?;
+                    report(
+                        name,
+                        output,
+                        &format!("copied {} to {}", source.display(), target.display()),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn undo_files(
+        name: &str,
+        mode: FileMode,
+        mappings: &[FileMapping],
+        files: &WrittenFiles,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        for FileMapping { source, target } in mappings.iter().rev() {
+            let is_own = match mode {
+                FileMode::Link => Self::is_mapping_applied(mode, source, target)?,
+                // copies made from an older version of the source are still pde's
+                FileMode::Copy => files.is_written(target)?,
+            };
+            if !is_own {
+                if path_exists(target) {
+                    report(
+                        name,
+                        output,
+                        &format!("{} was changed, keeping it", target.display()),
+                    );
+                }
+                continue;
+            }
+
+            Self::remove_written(name, target, files, output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes room for writing the target, `false` when the conflict policy keeps the
+    /// existing file. Outdated files written by pde itself are never a conflict
+    fn clear_target(
+        name: &str,
+        target: &Path,
+        on_conflict: ConflictPolicy,
+        is_written: bool,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<bool> {
+        if is_written {
+            remove_path(target)?;
+            report(name, output, &format!("updating {}", target.display()));
+            return Ok(true);
+        }
+
+        if !path_exists(target) {
+            return Ok(true);
+        }
+
+        match on_conflict {
+            ConflictPolicy::Skip => {
+                report(
+                    name,
+                    output,
+                    &format!("{} already exists, skipping", target.display()),
+                );
+                return Ok(false);
+            }
+            ConflictPolicy::Backup => {
+                let backup = Self::backup_path(target)?;
+                fs::rename(target, &backup)?;
+                report(
+                    name,
+                    output,
+                    &format!(
+                        "moved existing {} to {}",
+                        target.display(),
+                        backup.display()
+                    ),
+                );
+            }
+            ConflictPolicy::Overwrite => {
+                remove_path(target)?;
+                report(name, output, &format!("overwriting {}", target.display()));
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Removes a file written by pde and restores the backup of the file it replaced
+    fn remove_written(
+        name: &str,
+        target: &Path,
+        files: &WrittenFiles,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        remove_path(target)?;
+        files.forget(target)?;
+        report(name, output, &format!("removed {}", target.display()));
+
+        let backup = Self::backup_base_path(target);
+        if path_exists(&backup) {
+            fs::rename(&backup, target)?;
+            report(name, output, &format!("restored {}", target.display()));
+        }
+
+        Ok(())
+    }
+
+    fn is_mapping_applied(mode: FileMode, source: &Path, target: &Path) -> Result<bool> {
+        match mode {
+            FileMode::Link => Ok(is_symlink_to(target, source)),
+            FileMode::Copy => Ok(path_exists(target)
+                && !target.is_symlink()
+                && path_exists(source)
+                && same_content(source, target)?),
+        }
+    }
+
+    fn backup_base_path(target: &Path) -> PathBuf {
+        let mut backup = target.as_os_str().to_owned();
+        backup.push(".pde-backup");
+        PathBuf::from(backup)
+    }
+
+    /// `<target>.pde-backup`, or a timestamped variant when that one is taken
+    fn backup_path(target: &Path) -> Result<PathBuf> {
+        let backup = Self::backup_base_path(target);
+        if !path_exists(&backup) {
+            return Ok(backup);
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut backup = backup.into_os_string();
+        backup.push(format!(".{}", timestamp));
+        Ok(PathBuf::from(backup))
+    }
+
+    fn execute_template(
+        name: &str,
+        source: &Path,
+        dest: &Path,
+        content: &str,
+        on_conflict: ConflictPolicy,
+        files: &WrittenFiles,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        if fs::read_to_string(dest).is_ok_and(|current| current == content) {
+            report(name, output, &format!("{} unchanged", dest.display()));
+            return Ok(());
+        }
+
+        if !Self::clear_target(name, dest, on_conflict, files.is_written(dest)?, output)? {
+            return Ok(());
+        }
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(dest, content)?;
+        files.record(dest)?;
+        report(
+            name,
+            output,
+            &format!("rendered {} to {}", source.display(), dest.display()),
+        );
+
+        Ok(())
+    }
+
+    fn execute_script(
+        name: &str,
+        script: &str,
+        timeout: Option<Duration>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        report(name, output, "runnning script");
+        Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .pde_run_prefixed(name, timeout, output)?;
+        Ok(())
+    }
+
+    fn execute_archive(
+        cache: &PackageCache,
+        target_dir: &Path,
+        packages: &[ArchivePackage],
+        strip_components: usize,
+        target_name: &Option<String>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        for package in packages {
+            let package_dir = Self::package_dir(target_dir, &package.url, target_name)?;
+            let buffer = cache.get(package, timeout)?;
+
+            let format = ArchiveFormat::detect(&package.url, &buffer)?;
+            extract_archive(buffer, format, &package_dir, strip_components)?;
+        }
+
+        Ok(())
+    }
+
+    fn undo_archive(
+        name: &str,
+        target_dir: &Path,
+        packages: &[ArchivePackage],
+        target_name: &Option<String>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        for package in packages {
+            let package_dir = Self::package_dir(target_dir, &package.url, target_name)?;
+            if package_dir.exists() {
+                report(name, output, &format!("removing {}", package_dir.display()));
+                fs::remove_dir_all(&package_dir)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_service(
+        name: &str,
+        unit: &str,
+        unit_file: Option<&FileMapping>,
+        enable: bool,
+        start: bool,
+        restart_on_change: bool,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        let mut changed = false;
+        if let Some(FileMapping { source, target }) = unit_file {
+            let content = fs::read(source)
+                .map_err(|err| anyhow!("cannot read {}: {}", source.display(), err))?;
+            if fs::read(target).is_ok_and(|current| current == content) {
+                report(name, output, &format!("{} unchanged", target.display()));
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(target, content)?;
+                report(name, output, &format!("installed {}", target.display()));
+
+                Systemd::daemon_reload()?;
+                changed = true;
+            }
+        }
+
+        if enable {
+            if Systemd::is_enabled(unit)? {
+                report(name, output, &format!("{} already enabled", unit));
+            } else {
+                Systemd::enable(unit)?;
+                report(name, output, &format!("enabled {}", unit));
+            }
+        }
+
+        let active = Systemd::is_active(unit)?;
+        if active && changed && restart_on_change {
+            Systemd::restart(unit)?;
+            report(name, output, &format!("restarted {}", unit));
+        } else if !active && start {
+            Systemd::start(unit)?;
+            report(name, output, &format!("started {}", unit));
+        } else if active {
+            report(name, output, &format!("{} already running", unit));
+        }
+
+        Ok(())
+    }
+
+    /// Stops and disables the unit when the step started, enabled or installed it
+    fn undo_service(
+        name: &str,
+        unit: &str,
+        unit_file: Option<&FileMapping>,
+        enable: bool,
+        start: bool,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        let installed = unit_file.is_some();
+        if (start || installed) && Systemd::is_active(unit)? {
+            Systemd::stop(unit)?;
+            report(name, output, &format!("stopped {}", unit));
+        }
+
+        if (enable || installed) && Systemd::is_enabled(unit)? {
+            Systemd::disable(unit)?;
+            report(name, output, &format!("disabled {}", unit));
+        }
+
+        let Some(FileMapping { source, target }) = unit_file else {
+            return Ok(());
+        };
+
+        if Self::is_mapping_applied(FileMode::Copy, source, target)? {
+            fs::remove_file(target)?;
+            Systemd::daemon_reload()?;
+            report(name, output, &format!("removed {}", target.display()));
+        } else if path_exists(target) {
+            report(
+                name,
+                output,
+                &format!("{} was changed, keeping it", target.display()),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn package_dir(
+        target_dir: &Path,
+        package_url: &str,
+        target_name: &Option<String>,
+    ) -> Result<PathBuf> {
+        match target_name {
+            Some(target_name) => Ok(target_dir.join(target_name)),
+            None => Ok(target_dir.join(Self::package_name_from_path(package_url)?)),
+        }
+    }
+
+    fn package_name_from_path(url: &str) -> Result<String> {
+        let name = url
+            .rsplit('/')
+            .next()
+            .ok_or(anyhow!("cannot retrieve package name from url: {}", url))?;
+
+        Ok(archive_stem(name).to_owned())
+    }
+}
+
+/// Modules and steps without a `when` condition are always installed
+/// Prints a step message and passes it to the install log
+fn report(name: &str, output: &(dyn Fn(&str) + Sync), message: &str) {
+    println!("[{}] {}", name, message);
+    output(message);
+}
+
+fn is_condition_met(condition: Option<&Condition>) -> bool {
+    let Some(condition) = condition else {
+        return true;
+    };
+
+    if !condition.distro.is_empty() {
+        let distro = Host::distro().unwrap_or_default();
+        if !condition.distro.contains(&distro) {
+            return false;
+        }
+    }
+
+    if !condition.hostname.is_empty() {
+        let hostname = Host::hostname().unwrap_or_default();
+        if !condition.hostname.contains(&hostname) {
+            return false;
+        }
+    }
+
+    if !condition
+        .binary
+        .iter()
+        .all(|binary| Host::has_binary(binary))
+    {
+        return false;
+    }
+
+    let env_matches = condition
+        .env
+        .iter()
+        .all(|(name, expected)| match env::var(name) {
+            Ok(value) => expected == "*" || &value == expected,
+            Err(_) => false,
+        });
+    if !env_matches {
+        return false;
+    }
+
+    match condition.battery {
+        Some(battery) => Host::has_battery() == battery,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pde-step-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn step(action: StepAction) -> ProfileStep {
+        ProfileStep {
+            name: "test".to_owned(),
+            check: None,
+            parallel: false,
+            policy: StepPolicy::default(),
+            action,
+        }
+    }
+
+    fn run(step: &ProfileStep, dir: &Path) -> Result<Execution> {
+        step.execute(&PackageCache::new(dir, true), &files(dir), &|_| {})
+    }
+
+    fn files(dir: &Path) -> WrittenFiles {
+        WrittenFiles::load(dir).unwrap()
+    }
+
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["-c", "user.name=pde", "-c", "user.email=pde@localhost"])
+            .args(args)
+            .pde_run()
+            .unwrap();
+        String::from_utf8(output).unwrap().trim().to_owned()
+    }
+
+    /// Bare upstream repo with one commit and a working clone to push further commits from
+    fn upstream(dir: &Path) -> (String, PathBuf) {
+        let work = dir.join("work");
+        fs::create_dir_all(&work).unwrap();
+        git(&work, &["init", "-q", "-b", "main"]);
+        commit(&work, "first");
+        let url = dir.join("upstream.git").display().to_string();
+        git(dir, &["clone", "-q", "--bare", "work", &url]);
+        git(&work, &["remote", "add", "origin", &url]);
+        (url, work)
+    }
+
+    fn commit(repo: &Path, content: &str) -> String {
+        fs::write(repo.join("file"), content).unwrap();
+        git(repo, &["add", "file"]);
+        git(repo, &["commit", "-q", "-m", content]);
+        git(repo, &["rev-parse", "HEAD"])
+    }
+
+    fn git_step(url: &str, dest: &Path, rev: Option<String>) -> ProfileStep {
+        step(StepAction::Git {
+            url: url.to_owned(),
+            dest: dest.to_owned(),
+            rev,
+            branch: None,
+            depth: None,
+        })
+    }
+
+    #[test]
+    fn git_steps_clone_and_fast_forward_branches() {
+        let dir = scratch_dir("git-branch");
+        let (url, work) = upstream(&dir);
+        let dest = dir.join("dest");
+        let step = git_step(&url, &dest, None);
+
+        run(&step, &dir).unwrap();
+        let second = commit(&work, "second");
+        git(&work, &["push", "-q", "origin", "main"]);
+        run(&step, &dir).unwrap();
+
+        assert!(step.tracks_upstream());
+        assert_eq!(Git::head(&dest).unwrap(), second);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn git_steps_check_out_pinned_revisions() {
+        let dir = scratch_dir("git-rev");
+        let (url, work) = upstream(&dir);
+        let first = git(&work, &["rev-parse", "HEAD"]);
+        commit(&work, "second");
+        git(&work, &["push", "-q", "origin", "main"]);
+        let dest = dir.join("dest");
+        let step = git_step(&url, &dest, Some(first.clone()));
+
+        run(&step, &dir).unwrap();
+
+        assert!(!step.tracks_upstream());
+        assert_eq!(Git::head(&dest).unwrap(), first);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn git_undo_keeps_checkouts_with_local_work() {
+        let dir = scratch_dir("git-undo");
+        let (url, _) = upstream(&dir);
+        let dest = dir.join("dest");
+        let step = git_step(&url, &dest, None);
+        run(&step, &dir).unwrap();
+
+        fs::write(dest.join("file"), "changed").unwrap();
+        step.undo(&files(&dir), &|_| {}).unwrap();
+        assert!(dest.exists());
+
+        commit(&dest, "local");
+        step.undo(&files(&dir), &|_| {}).unwrap();
+        assert!(dest.exists());
+
+        git(&dest, &["reset", "-q", "--hard", "origin/main"]);
+        step.undo(&files(&dir), &|_| {}).unwrap();
+        assert!(!dest.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn copy_step(dir: &Path, on_conflict: ConflictPolicy) -> ProfileStep {
+        step(StepAction::Files {
+            mode: FileMode::Copy,
+            mappings: vec![FileMapping {
+                source: dir.join("source"),
+                target: dir.join("rc"),
+            }],
+            on_conflict,
+        })
+    }
+
+    fn backups(dir: &Path) -> Vec<String> {
+        let mut backups: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("rc.pde-backup"))
+            .collect();
+        backups.sort();
+        backups
+    }
+
+    #[test]
+    fn copy_backup_keeps_one_backup_of_the_user_file() {
+        let dir = scratch_dir("copy-backup");
+        let step = copy_step(&dir, ConflictPolicy::Backup);
+        fs::write(dir.join("rc"), "user").unwrap();
+        fs::write(dir.join("source"), "v1").unwrap();
+
+        run(&step, &dir).unwrap();
+        fs::write(dir.join("source"), "v2").unwrap();
+        run(&step, &dir).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "v2");
+        assert_eq!(backups(&dir), ["rc.pde-backup"]);
+
+        step.undo(&files(&dir), &|_| {}).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "user");
+        assert!(backups(&dir).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_skip_updates_own_copies_only() {
+        let dir = scratch_dir("copy-skip");
+        let step = copy_step(&dir, ConflictPolicy::Skip);
+        fs::write(dir.join("source"), "v1").unwrap();
+
+        run(&step, &dir).unwrap();
+        fs::write(dir.join("source"), "v2").unwrap();
+        run(&step, &dir).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "v2");
+
+        fs::write(dir.join("rc"), "edited").unwrap();
+        fs::write(dir.join("source"), "v3").unwrap();
+        run(&step, &dir).unwrap();
+        step.undo(&files(&dir), &|_| {}).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "edited");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_overwrite_replaces_user_files_without_backup() {
+        let dir = scratch_dir("copy-overwrite");
+        let step = copy_step(&dir, ConflictPolicy::Overwrite);
+        fs::write(dir.join("rc"), "user").unwrap();
+        fs::write(dir.join("source"), "v1").unwrap();
+
+        run(&step, &dir).unwrap();
+        fs::write(dir.join("source"), "v2").unwrap();
+        run(&step, &dir).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "v2");
+        assert!(backups(&dir).is_empty());
+
+        step.undo(&files(&dir), &|_| {}).unwrap();
+
+        assert!(!path_exists(&dir.join("rc")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn link_backup_restores_the_user_file_on_undo() {
+        let dir = scratch_dir("link-backup");
+        let step = step(StepAction::Files {
+            mode: FileMode::Link,
+            mappings: vec![FileMapping {
+                source: dir.join("source"),
+                target: dir.join("rc"),
+            }],
+            on_conflict: ConflictPolicy::Backup,
+        });
+        fs::write(dir.join("rc"), "user").unwrap();
+        fs::write(dir.join("source"), "v1").unwrap();
+
+        run(&step, &dir).unwrap();
+        fs::write(dir.join("source"), "v2").unwrap();
+        run(&step, &dir).unwrap();
+
+        assert!(is_symlink_to(&dir.join("rc"), &dir.join("source")));
+        assert_eq!(backups(&dir), ["rc.pde-backup"]);
+
+        step.undo(&files(&dir), &|_| {}).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "user");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn template_backup_restores_the_hand_written_config() {
+        let dir = scratch_dir("template");
+        let template = |content: &str| {
+            step(StepAction::Template {
+                source: dir.join("rc.tpl"),
+                dest: dir.join("rc"),
+                content: content.to_owned(),
+                on_conflict: ConflictPolicy::Backup,
+            })
+        };
+        fs::write(dir.join("rc"), "user").unwrap();
+
+        run(&template("v1"), &dir).unwrap();
+        run(&template("v2"), &dir).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "v2");
+        assert_eq!(backups(&dir), ["rc.pde-backup"]);
+
+        template("v2").undo(&files(&dir), &|_| {}).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("rc")).unwrap(), "user");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
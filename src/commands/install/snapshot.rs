@@ -0,0 +1,167 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::profile_module::{ProfileModule, StepAction};
+use crate::modules::{host::Host, package_manager::PackageManager, systemd::Systemd};
+
+/// Packages and enabled systemd user units found on the host
+pub struct HostSnapshot {
+    packages: BTreeMap<PackageManager, Vec<String>>,
+    user_units: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SnapshotModule<'a> {
+    name: &'a str,
+    description: String,
+    steps: Vec<SnapshotStep<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum SnapshotStep<'a> {
+    Tool {
+        name: &'a str,
+        tool: &'a str,
+        packages: &'a [String],
+    },
+    Service {
+        name: &'a str,
+        service: &'a str,
+        start: bool,
+    },
+}
+
+impl HostSnapshot {
+    /// Inspects the detected system package manager, flatpak, cargo and systemd,
+    /// skipping package managers that cannot be queried
+    pub fn capture() -> Result<Self> {
+        let mut managers = PackageManager::detect().into_iter().collect::<Vec<_>>();
+        managers.extend(
+            [PackageManager::Flatpak, PackageManager::Cargo]
+                .into_iter()
+                .filter(|manager| manager.is_available()),
+        );
+
+        let mut packages = BTreeMap::new();
+        for manager in managers {
+            match manager.installed_packages() {
+                Ok(installed) => {
+                    packages.insert(manager, installed);
+                }
+                Err(err) => eprintln!("skipping {} packages: {}", manager.name(), err),
+            }
+        }
+
+        // template units like `foo@.service` can only be enabled through an instance
+        let user_units = if Host::has_binary("systemctl") {
+            Systemd::enabled_user_units()?
+                .into_iter()
+                .filter(|unit| !unit.contains("@."))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Ok(Self {
+            packages,
+            user_units,
+        })
+    }
+
+    /// Module definition with a tool step per package manager and a service step per
+    /// enabled user unit
+    pub fn module_toml(&self, name: &str) -> Result<String> {
+        let mut steps = self
+            .packages
+            .iter()
+            .filter(|(_, packages)| !packages.is_empty())
+            .map(|(manager, packages)| SnapshotStep::Tool {
+                name: manager.name(),
+                tool: manager.name(),
+                packages,
+            })
+            .collect::<Vec<_>>();
+
+        // units are only known to be enabled, not running
+        steps.extend(self.user_units.iter().map(|unit| SnapshotStep::Service {
+            name: unit,
+            service: unit,
+            start: false,
+        }));
+
+        let module = SnapshotModule {
+            name,
+            description: format!("snapshot of {}", Host::hostname()?),
+            steps,
+        };
+
+        Ok(toml::to_string_pretty(&module)?)
+    }
+
+    /// Tools definitions for the package managers used by `module_toml`
+    pub fn tools_toml(&self) -> Result<String> {
+        let tools = self
+            .packages
+            .keys()
+            .map(|manager| (manager.name(), manager.tool_config()))
+            .collect::<BTreeMap<_, _>>();
+
+        Ok(toml::to_string_pretty(&tools)?)
+    }
+
+    /// Prints packages installed on the host but missing from the profile and the
+    /// other way around, per package manager
+    pub fn print_diff(&self, profile: &[ProfileModule]) {
+        let mut wanted = BTreeMap::<PackageManager, BTreeSet<&str>>::new();
+        for step in profile.iter().flat_map(|module| &module.steps) {
+            if let StepAction::Tool { config, packages } = &step.action {
+                if let Some(manager) = PackageManager::from_cmd(&config.cmd) {
+                    wanted
+                        .entry(manager)
+                        .or_default()
+                        .extend(packages.iter().map(String::as_str));
+                }
+            }
+        }
+
+        let managers = self
+            .packages
+            .keys()
+            .chain(wanted.keys())
+            .collect::<BTreeSet<_>>();
+        for manager in managers {
+            let Some(installed) = self.packages.get(manager) else {
+                println!("{}: not available on this machine", manager.name());
+                continue;
+            };
+
+            let installed = installed
+                .iter()
+                .map(String::as_str)
+                .collect::<BTreeSet<_>>();
+            let wanted = wanted.get(manager).cloned().unwrap_or_default();
+
+            let extra = installed.difference(&wanted).collect::<Vec<_>>();
+            let missing = wanted.difference(&installed).collect::<Vec<_>>();
+            if extra.is_empty() && missing.is_empty() {
+                println!("{}: in sync", manager.name());
+                continue;
+            }
+
+            if !extra.is_empty() {
+                println!("{}: installed but not in the profile", manager.name());
+                for package in extra {
+                    println!("   + {}", package);
+                }
+            }
+            if !missing.is_empty() {
+                println!("{}: in the profile but not installed", manager.name());
+                for package in missing {
+                    println!("   - {}", package);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::config::profiles::{
+    module_config::{ModuleConfig, StepKind},
+    profiles_config::ProfilesConfig,
+    tools_config::ToolConfig,
+};
+
+/// Problems found in profiles.toml and the definitions it references, errors make the
+/// configuration unusable while warnings point at likely mistakes
+#[derive(Default)]
+pub struct Validation {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Validation {
+    /// Checks every definition file instead of stopping at the first error like
+    /// `ProfilesConfig::parse` does
+    pub fn run(input_path: &Option<PathBuf>) -> Self {
+        let mut validation = Self::default();
+
+        let mut config = match ProfilesConfig::parse_file(input_path) {
+            Ok(config) => config,
+            Err(err) => {
+                validation.error(err.to_string());
+                return validation;
+            }
+        };
+
+        if let Some(path) = config.tools_path() {
+            match ToolConfig::parse(&path) {
+                Ok(tools) => config.tools.extend(tools),
+                Err(err) => validation.error(err.to_string()),
+            }
+        }
+
+        let mut sources = config
+            .modules
+            .keys()
+            .map(|name| (name.clone(), PathBuf::from("profiles.toml")))
+            .collect::<HashMap<_, _>>();
+        if let Some(dir_path) = config.modules_path() {
+            match ModuleConfig::module_files(&dir_path) {
+                Ok(files) => {
+                    for path in files {
+                        match ModuleConfig::parse(&path) {
+                            Ok(module) => {
+                                validation.add_module(&mut config, &mut sources, path, module)
+                            }
+                            Err(err) => {
+                                validation.error(err.to_string());
+                                // profiles using the module would be reported as well otherwise
+                                let module = Self::unparsed_module(&path);
+                                validation.add_module(&mut config, &mut sources, path, module);
+                            }
+                        }
+                    }
+                }
+                Err(err) => validation.error(err.to_string()),
+            }
+        }
+
+        validation.check_steps(&config, &sources);
+        validation.check_tools(&config, &sources);
+        validation.check_profiles(&config);
+
+        validation
+    }
+
+    fn add_module(
+        &mut self,
+        config: &mut ProfilesConfig,
+        sources: &mut HashMap<String, PathBuf>,
+        path: PathBuf,
+        module: ModuleConfig,
+    ) {
+        if let Some(source) = sources.get(&module.name) {
+            self.error(format!(
+                "duplicate {} module in {} and {}",
+                module.name,
+                source.display(),
+                path.display()
+            ));
+            return;
+        }
+
+        sources.insert(module.name.clone(), path);
+        config.modules.insert(module.name.clone(), module);
+    }
+
+    /// Stand-in without steps for a module file that failed to parse, named after its
+    /// `name` field if that can still be read
+    fn unparsed_module(path: &Path) -> ModuleConfig {
+        let table = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| content.parse::<toml::Table>().ok())
+            .unwrap_or_default();
+        let name = table
+            .get("name")
+            .and_then(|name| name.as_str())
+            .map(|name| name.to_owned())
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            });
+        let requires = table
+            .get("requires")
+            .and_then(|requires| requires.as_array())
+            .map(|requires| {
+                requires
+                    .iter()
+                    .filter_map(|name| Some(name.as_str()?.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        ModuleConfig {
+            name,
+            description: None,
+            requires,
+            when: None,
+            parallel: false,
+            before: vec![],
+            after: vec![],
+            steps: vec![],
+        }
+    }
+
+    fn check_steps(&mut self, config: &ProfilesConfig, sources: &HashMap<String, PathBuf>) {
+        let mut names = config.modules.keys().collect::<Vec<_>>();
+        names.sort();
+
+        for name in names {
+            for step in config.modules[name].duplicate_steps() {
+                self.error(format!(
+                    "{}: {} module has more than one {} step, step names have to be unique",
+                    sources[name].display(),
+                    name,
+                    step
+                ));
+            }
+        }
+    }
+
+    fn check_tools(&mut self, config: &ProfilesConfig, sources: &HashMap<String, PathBuf>) {
+        let mut names = config.modules.keys().collect::<Vec<_>>();
+        names.sort();
+
+        for name in names {
+            for step in &config.modules[name].steps {
+                if let StepKind::Tool { tool, .. } = &step.kind {
+                    if !config.tools.contains_key(tool) {
+                        self.error(format!(
+                            "{}: {}/{} step uses undefined {} tool",
+                            sources[name].display(),
+                            name,
+                            step.common.name,
+                            tool
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_profiles(&mut self, config: &ProfilesConfig) {
+        if let Some(default_profile) = &config.default_profile {
+            if !config.profiles.contains_key(default_profile) {
+                self.error(format!(
+                    "default_profile '{}' profile defintion not found",
+                    default_profile
+                ));
+            }
+        }
+
+        let mut names = config.profiles.keys().collect::<Vec<_>>();
+        names.sort();
+
+        let mut referenced = HashSet::new();
+        for name in names {
+            match config.resolve_modules(std::slice::from_ref(name)) {
+                Ok(modules) => referenced.extend(modules.into_iter().map(|module| &module.name)),
+                Err(err) => self.error(format!("'{}' profile: {}", name, err)),
+            }
+        }
+
+        let mut unreferenced = config
+            .modules
+            .keys()
+            .filter(|name| !referenced.contains(name))
+            .collect::<Vec<_>>();
+        unreferenced.sort();
+        for name in unreferenced {
+            self.warnings
+                .push(format!("{} module is not used by any profile", name));
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        if !self.errors.contains(&message) {
+            self.errors.push(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// profiles.toml using a `desk` module from the modules directory
+    fn validate(name: &str, module: &str) -> Validation {
+        let dir = env::temp_dir().join(format!("pde-validate-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("modules")).unwrap();
+        fs::write(
+            dir.join("profiles.toml"),
+            "modules_definitions = \"modules\"\n[profiles]\ndesk = [\"desk\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("modules/desk.toml"), module).unwrap();
+
+        let validation = Validation::run(&Some(dir.join("profiles.toml")));
+        fs::remove_dir_all(dir).unwrap();
+        validation
+    }
+
+    #[test]
+    fn valid_modules_have_no_errors() {
+        let validation = validate(
+            "valid",
+            "name = \"desk\"\n[[steps]]\nname = \"s\"\nscript = \"true\"\n",
+        );
+
+        assert!(validation.errors.is_empty());
+        assert!(validation.warnings.is_empty());
+    }
+
+    #[test]
+    fn step_errors_point_at_the_failing_step() {
+        let validation = validate(
+            "span",
+            "name = \"desk\"\n\n[[steps]]\nname = \"a\"\nscript = \"true\"\n\n[[steps]]\nname = \"b\"\nscript = \"true\"\nbogus = 1\n",
+        );
+
+        assert_eq!(validation.errors.len(), 1);
+        let error = &validation.errors[0];
+        assert!(error.contains("line 7, column 1"), "{}", error);
+        assert!(
+            error.contains("b script step: unknown field `bogus`"),
+            "{}",
+            error
+        );
+        assert!(error.contains("`retries`"), "{}", error);
+    }
+
+    #[test]
+    fn duplicate_step_names_and_undefined_tools_are_errors() {
+        let validation = validate(
+            "steps",
+            r#"
+            name = "desk"
+            steps = [
+                { name = "s", script = "true" },
+                { name = "s", tool = "missing", packages = ["bat"] },
+            ]
+            "#,
+        );
+
+        assert_eq!(validation.errors.len(), 2);
+        assert!(validation.errors[0]
+            .ends_with("desk module has more than one s step, step names have to be unique"));
+        assert!(validation.errors[1].ends_with("desk/s step uses undefined missing tool"));
+    }
+}
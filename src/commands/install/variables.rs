@@ -0,0 +1,249 @@
+use anyhow::{bail, Result};
+use regex::{Captures, Regex};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    config::profiles::{
+        module_config::{ArchivePackage, ModuleStep, StepCommon, StepKind},
+        profiles_config::ProfilesConfig,
+    },
+    modules::host::Host,
+    utils::path_extensions::PathExtensions,
+};
+
+/// Values for `{{ name }}` placeholders: profile and global `variables`,
+/// host facts as `host.*` and environment variables as `env.*`
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    /// Host facts that cannot be read are left undefined, so that only placeholders
+    /// using them fail
+    pub fn new(config: &ProfilesConfig, profile_names: &[String]) -> Result<Self> {
+        let host_facts = [
+            ("host.hostname", Host::hostname()),
+            ("host.username", Host::username()),
+            ("host.distro", Host::distro()),
+        ];
+        let mut values: HashMap<String, String> = host_facts
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_owned(), value.ok()?)))
+            .collect();
+
+        values.extend(config.variables.clone());
+        for profile_name in profile_names {
+            values.extend(config.expand_profile(profile_name)?.variables);
+        }
+
+        Ok(Self { values })
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        match name.strip_prefix("env.") {
+            Some(env_name) => env::var(env_name).ok(),
+            None => self.values.get(name).cloned(),
+        }
+    }
+
+    /// Replaces all placeholders, failing with the names of undefined variables.
+    /// `\{{` is written out as a literal `{{`, and braces not enclosing a name starting
+    /// with a letter or `_`, like `{{.Names}}`, are kept as they are
+    pub fn render(&self, input: &str) -> Result<String> {
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+        let pattern = PATTERN.get_or_init(|| {
+            Regex::new(r"\\\{\{|\{\{\s*([A-Za-z_][\w.-]*)\s*\}\}")
+                .expect("valid placeholder pattern")
+        });
+
+        let mut undefined = vec![];
+        let output = pattern.replace_all(input, |captures: &Captures| {
+            let Some(name) = captures.get(1) else {
+                return "{{".to_owned();
+            };
+
+            self.get(name.as_str()).unwrap_or_else(|| {
+                undefined.push(name.as_str().to_owned());
+                String::new()
+            })
+        });
+
+        if !undefined.is_empty() {
+            bail!("undefined variables: {}", undefined.join(", "));
+        }
+
+        Ok(output.into_owned())
+    }
+
+    /// Renders placeholders in every string field of the step except its name
+    pub fn interpolate_step(&self, step: ModuleStep) -> Result<ModuleStep> {
+        let ModuleStep { common, kind } = step;
+        let kind = match kind {
+            StepKind::Tool { tool, packages } => StepKind::Tool {
+                tool,
+                packages: self.render_all(packages)?,
+            },
+            StepKind::Archive {
+                extract_to,
+                packages,
+                strip_components,
+                target_name,
+            } => StepKind::Archive {
+                extract_to: self.render_path(&extract_to)?,
+                packages: packages
+                    .into_iter()
+                    .map(|package| {
+                        Ok(ArchivePackage {
+                            url: self.render(&package.url)?,
+                            sha256: self.render_optional(package.sha256)?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                strip_components,
+                target_name: self.render_optional(target_name)?,
+            },
+            StepKind::Packages {
+                packages,
+                manager,
+                overrides,
+            } => StepKind::Packages {
+                packages: self.render_all(packages)?,
+                manager,
+                overrides: overrides
+                    .into_iter()
+                    .map(|(manager, names)| Ok((manager, self.render_map(names)?)))
+                    .collect::<Result<_>>()?,
+            },
+            StepKind::Git {
+                git,
+                dest,
+                rev,
+                branch,
+                depth,
+            } => StepKind::Git {
+                git: self.render(&git)?,
+                dest: self.render_path(&dest)?,
+                rev: self.render_optional(rev)?,
+                branch: self.render_optional(branch)?,
+                depth,
+            },
+            StepKind::Link { link, on_conflict } => StepKind::Link {
+                link: self.render_map(link)?,
+                on_conflict,
+            },
+            StepKind::Copy { copy, on_conflict } => StepKind::Copy {
+                copy: self.render_map(copy)?,
+                on_conflict,
+            },
+            StepKind::Template {
+                template,
+                dest,
+                on_conflict,
+            } => StepKind::Template {
+                template: self.render(&template)?,
+                dest: self.render_path(&dest)?,
+                on_conflict,
+            },
+            StepKind::Script { script, undo } => StepKind::Script {
+                script: self.render(&script)?,
+                undo: self.render_optional(undo)?,
+            },
+            StepKind::Service {
+                service,
+                unit_file,
+                enable,
+                start,
+                restart_on_change,
+            } => StepKind::Service {
+                service: self.render(&service)?,
+                unit_file: self.render_optional(unit_file)?,
+                enable,
+                start,
+                restart_on_change,
+            },
+        };
+
+        Ok(ModuleStep {
+            common: StepCommon {
+                check: self.render_optional(common.check)?,
+                ..common
+            },
+            kind,
+        })
+    }
+
+    fn render_all(&self, values: Vec<String>) -> Result<Vec<String>> {
+        values.iter().map(|value| self.render(value)).collect()
+    }
+
+    fn render_optional(&self, value: Option<String>) -> Result<Option<String>> {
+        value.map(|value| self.render(&value)).transpose()
+    }
+
+    fn render_map<M>(&self, values: M) -> Result<M>
+    where
+        M: IntoIterator<Item = (String, String)> + FromIterator<(String, String)>,
+    {
+        values
+            .into_iter()
+            .map(|(key, value)| Ok((self.render(&key)?, self.render(&value)?)))
+            .collect()
+    }
+
+    fn render_path(&self, path: &Path) -> Result<PathBuf> {
+        PathBuf::from(self.render(&path.to_string_lossy())?).pde_resolve()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        Variables {
+            values: HashMap::from([
+                ("name".to_owned(), "pde".to_owned()),
+                ("host.hostname".to_owned(), "desk".to_owned()),
+            ]),
+        }
+    }
+
+    #[test]
+    fn render_replaces_placeholders() {
+        let output = variables()
+            .render("{{name}} on {{ host.hostname }}")
+            .unwrap();
+
+        assert_eq!(output, "pde on desk");
+    }
+
+    #[test]
+    fn render_reports_all_undefined_variables() {
+        let err = variables()
+            .render("{{ a }} {{ name }} {{ b }}")
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "undefined variables: a, b");
+    }
+
+    #[test]
+    fn render_keeps_braces_not_enclosing_a_name() {
+        let input = "docker ps --format '{{.Names}}' {{ 1 }}";
+
+        assert_eq!(variables().render(input).unwrap(), input);
+    }
+
+    #[test]
+    fn render_writes_escaped_braces_literally() {
+        let output = variables()
+            .render(r"$\{{ secrets.token }} \{{name}}")
+            .unwrap();
+
+        assert_eq!(output, "${{ secrets.token }} {{name}}");
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::utils::fs_utils::path_exists;
+
+/// Digests of the files pde copied or rendered, so that later runs can replace their own
+/// outdated files while files created or edited by the user go through the conflict policy
+pub struct WrittenFiles {
+    path: PathBuf,
+    files: Mutex<WrittenFilesIndex>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct WrittenFilesIndex {
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+}
+
+impl WrittenFiles {
+    pub fn load(resource_root_dir: &Path) -> Result<Self> {
+        let path = resource_root_dir.join("install/files.toml");
+
+        let files = if path.exists() {
+            toml::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            WrittenFilesIndex::default()
+        };
+
+        Ok(Self {
+            path,
+            files: Mutex::new(files),
+        })
+    }
+
+    /// Whether the target still holds exactly what pde wrote there
+    pub fn is_written(&self, target: &Path) -> Result<bool> {
+        let files = self.files.lock().expect("written files lock poisoned");
+        let Some(digest) = files.files.get(&target.display().to_string()) else {
+            return Ok(false);
+        };
+
+        Ok(path_exists(target) && &Self::digest(target)? == digest)
+    }
+
+    pub fn record(&self, target: &Path) -> Result<()> {
+        let digest = Self::digest(target)?;
+        let mut files = self.files.lock().expect("written files lock poisoned");
+        files.files.insert(target.display().to_string(), digest);
+        self.save(&files)
+    }
+
+    pub fn forget(&self, target: &Path) -> Result<()> {
+        let mut files = self.files.lock().expect("written files lock poisoned");
+        if files.files.remove(&target.display().to_string()).is_some() {
+            self.save(&files)?;
+        }
+
+        Ok(())
+    }
+
+    fn save(&self, files: &WrittenFilesIndex) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, toml::to_string(files)?)?;
+
+        Ok(())
+    }
+
+    /// Hash of the file, or of the relative paths and contents of all files in the directory
+    fn digest(path: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        Self::hash_path(path, Path::new(""), &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    fn hash_path(path: &Path, relative: &Path, hasher: &mut Sha256) -> Result<()> {
+        if !path.is_dir() {
+            hasher.update(relative.display().to_string().as_bytes());
+            hasher.update([0]);
+            hasher.update(fs::read(path)?);
+            hasher.update([0]);
+            return Ok(());
+        }
+
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for name in entries {
+            Self::hash_path(&path.join(&name), &relative.join(&name), hasher)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::{
+    commands::install::{
+        install_log::InstallLog, install_state::InstallState, profile_module::ProfileModule,
+        variables::Variables, written_files::WrittenFiles,
+    },
+    config::{profiles::profiles_config::ProfilesConfig, Config},
+};
+
+#[derive(Args)]
+pub struct UninstallCommand {
+    /// Profiles to uninstall, defaults to `default_profile`
+    #[arg(short, long)]
+    profile: Vec<String>,
+    /// Uninstall only the given modules, leaving their dependencies in place
+    #[arg(short, long)]
+    module: Vec<String>,
+    profiles_path: Option<PathBuf>,
+}
+
+pub struct UninstallCommandHandler {
+    resource_root_dir: PathBuf,
+}
+
+impl UninstallCommandHandler {
+    pub fn create(config: &Config) -> Self {
+        Self {
+            resource_root_dir: config.general.resource_root_dir.clone(),
+        }
+    }
+
+    pub fn handle(self, cmd: &UninstallCommand) -> Result<()> {
+        let config = ProfilesConfig::parse(&cmd.profiles_path)?;
+        let mut state = InstallState::load(&self.resource_root_dir)?;
+
+        let modules = if cmd.module.is_empty() {
+            let profile_names = config.profile_names(&cmd.profile)?;
+            ProfileModule::resolve(&config, &profile_names)?
+        } else {
+            let variables = Variables::new(&config, &config.profile_names(&cmd.profile)?)?;
+            ProfileModule::from_configs(&config, config.find_modules(&cmd.module)?, &variables)?
+        };
+
+        let files = WrittenFiles::load(&self.resource_root_dir)?;
+        let log = InstallLog::create(&self.resource_root_dir, "uninstall")?;
+        let result = Self::uninstall(&modules, &mut state, &files, &log);
+        log.print_summary();
+
+        result
+    }
+
+    fn uninstall(
+        modules: &[ProfileModule],
+        state: &mut InstallState,
+        files: &WrittenFiles,
+        log: &InstallLog,
+    ) -> Result<()> {
+        // dependents go first so nothing is removed while still in use
+        for module in modules.iter().rev() {
+            for step in module.steps.iter().rev() {
+                let key = module.step_key(step);
+                log.run(&key, |output| step.undo(files, output))
+                    .map_err(|err| err.context(format!("{} step could not be undone", key)))?;
+                state.forget(&key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -38,4 +38,22 @@ impl ParseUtils {
             .flatten()
             .collect())
     }
+
+    /// Accepts a single string as a one element list
+    pub fn parse_string_or_list<'de, D>(d: D) -> Result<Vec<String>, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum StringOrList {
+            String(String),
+            List(Vec<String>),
+        }
+
+        Ok(match StringOrList::deserialize(d)? {
+            StringOrList::String(value) => vec![value],
+            StringOrList::List(values) => values,
+        })
+    }
 }
@@ -1,3 +1,4 @@
 pub mod module_config;
+pub mod profile_config;
 pub mod profiles_config;
 pub mod tools_config;
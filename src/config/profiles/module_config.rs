@@ -1,41 +1,372 @@
-use crate::config::parse_utils::ParseUtils;
-use anyhow::{bail, Result};
-use serde::Deserialize;
-use std::{collections::HashMap, fs, path::PathBuf};
+use super::tools_config::ToolConfig;
+use crate::{config::parse_utils::ParseUtils, modules::package_manager::PackageManager};
+use anyhow::{anyhow, bail, Result};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::PathBuf,
+};
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ModuleConfig {
     pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub requires: Vec<String>,
+    pub when: Option<Condition>,
+    /// Runs all steps of the module concurrently
+    #[serde(default)]
+    pub parallel: bool,
+    /// Scripts run before the first and after the last step of the module that is installed
+    #[serde(default)]
+    pub before: Vec<String>,
+    #[serde(default)]
+    pub after: Vec<String>,
     pub steps: Vec<ModuleStep>,
 }
 
+/// Step of a module, given as a single table holding both the common and the kind
+/// specific fields
+#[derive(Debug, Clone)]
+pub struct ModuleStep {
+    pub common: StepCommon,
+    pub kind: StepKind,
+}
+
+/// Fields every kind of step has
 #[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
-pub enum ModuleStep {
+#[serde(deny_unknown_fields)]
+pub struct StepCommon {
+    pub name: String,
+    /// Script that exits 0 when the step is already satisfied
+    pub check: Option<String>,
+    pub when: Option<Condition>,
+    /// Runs concurrently with adjacent parallel steps of the module
+    #[serde(default)]
+    pub parallel: bool,
+    #[serde(default)]
+    pub policy: StepPolicy,
+}
+
+/// Steps have no explicit type, the kind tag is derived from their fields by `from_table`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StepKind {
     Tool {
-        name: String,
         tool: String,
         packages: Vec<String>,
     },
-    Zip {
-        name: String,
-        #[serde(deserialize_with = "ParseUtils::parse_path")]
-        extract_zip_to: PathBuf,
+    Archive {
+        #[serde(alias = "extract_zip_to", deserialize_with = "ParseUtils::parse_path")]
+        extract_to: PathBuf,
+        packages: Vec<ArchivePackage>,
+        #[serde(default)]
+        strip_components: usize,
+        target_name: Option<String>,
+    },
+    Packages {
+        /// Logical package names, installed as they are unless overridden
         packages: Vec<String>,
+        /// Package manager to use instead of the one detected on the host
+        manager: Option<PackageManager>,
+        /// Per-manager package names, e.g. `apt = { fd = "fd-find" }`
+        #[serde(default)]
+        overrides: HashMap<PackageManager, HashMap<String, String>>,
+    },
+    Git {
+        git: String,
+        #[serde(deserialize_with = "ParseUtils::parse_path")]
+        dest: PathBuf,
+        rev: Option<String>,
+        branch: Option<String>,
+        depth: Option<u32>,
+    },
+    Link {
+        /// Source paths relative to the profiles.toml directory mapped to their targets
+        link: BTreeMap<String, String>,
+        #[serde(default)]
+        on_conflict: ConflictPolicy,
+    },
+    Copy {
+        copy: BTreeMap<String, String>,
+        #[serde(default)]
+        on_conflict: ConflictPolicy,
+    },
+    Template {
+        /// Path relative to the profiles.toml directory. `{{ name }}` placeholders in the
+        /// file are rendered, other template syntax using `{{` has to be written as `\{{`
+        template: String,
+        #[serde(deserialize_with = "ParseUtils::parse_path")]
+        dest: PathBuf,
+        #[serde(default)]
+        on_conflict: ConflictPolicy,
     },
     Script {
-        name: String,
         script: String,
+        undo: Option<String>,
+    },
+    Service {
+        /// Systemd user unit, e.g. `syncthing.service`
+        service: String,
+        /// Unit file relative to the profiles.toml directory, installed into the user unit directory
+        unit_file: Option<String>,
+        /// Enable and start the unit, both default to true
+        enable: Option<bool>,
+        start: Option<bool>,
+        /// Restart the running unit when its unit file changed, defaults to true
+        restart_on_change: Option<bool>,
     },
 }
 
+/// Retry, timeout and failure handling, given as top level fields of any step
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StepPolicy {
+    /// Additional attempts after a failure, defaults to the tool's `retries`
+    pub retries: Option<u32>,
+    /// Seconds to wait between attempts
+    pub retry_delay: Option<u64>,
+    /// Seconds after which commands of tool and script steps are killed and archive
+    /// downloads are aborted
+    pub timeout: Option<u64>,
+    /// Report a failure of the step without aborting the install
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+impl StepPolicy {
+    const FIELDS: [&'static str; 4] = ["retries", "retry_delay", "timeout", "continue_on_error"];
+
+    /// Fills settings missing on the step from the tool definition
+    pub fn or_tool(self, tool: &ToolConfig) -> Self {
+        Self {
+            retries: self.retries.or(tool.retries),
+            retry_delay: self.retry_delay.or(tool.retry_delay),
+            timeout: self.timeout.or(tool.timeout),
+            continue_on_error: self.continue_on_error,
+        }
+    }
+}
+
+/// Host facts that all have to hold for a module or step to be installed
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// Any of the given `ID`s from /etc/os-release
+    #[serde(default, deserialize_with = "ParseUtils::parse_string_or_list")]
+    pub distro: Vec<String>,
+    #[serde(default, deserialize_with = "ParseUtils::parse_string_or_list")]
+    pub hostname: Vec<String>,
+    /// Binaries that all have to be found on PATH
+    #[serde(default, deserialize_with = "ParseUtils::parse_string_or_list")]
+    pub binary: Vec<String>,
+    /// Expected values of environment variables, `"*"` accepts any value that is set
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub battery: Option<bool>,
+}
+
+/// What to do when a link, copy or template target already exists
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Backup,
+    Skip,
+    Overwrite,
+}
+
+/// Archive url or path relative to the profiles.toml directory, optionally pinned to a
+/// sha256 digest: `"<url>"` or `{ url, sha256 }`
+#[derive(Debug, Clone)]
+pub struct ArchivePackage {
+    pub url: String,
+    pub sha256: Option<String>,
+}
+
+/// Table form of an archive package, misspelled keys are errors so that a digest is
+/// never silently ignored
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArchivePackageTable {
+    url: String,
+    sha256: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for ArchivePackage {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match toml::Value::deserialize(d)? {
+            toml::Value::String(url) => Ok(Self { url, sha256: None }),
+            toml::Value::Table(table) => {
+                let ArchivePackageTable { url, sha256 } =
+                    ArchivePackageTable::deserialize(toml::Value::Table(table))
+                        .map_err(|err| de::Error::custom(err.message()))?;
+                Ok(Self { url, sha256 })
+            }
+            value => Err(de::Error::custom(format!(
+                "invalid type: {}, expected an archive url or a {{ url, sha256 }} table",
+                value.type_str()
+            ))),
+        }
+    }
+}
+
+impl ModuleStep {
+    /// Fields identifying the kind of a step, tool and archive steps come first
+    /// because they list `packages` as well
+    const KINDS: [(&'static str, &'static str); 10] = [
+        ("tool", "tool"),
+        ("extract_to", "archive"),
+        ("extract_zip_to", "archive"),
+        ("git", "git"),
+        ("link", "link"),
+        ("copy", "copy"),
+        ("template", "template"),
+        ("script", "script"),
+        ("service", "service"),
+        ("packages", "packages"),
+    ];
+
+    /// Deserializes the step as the kind its fields identify
+    fn from_table(mut table: toml::Table) -> Result<Self> {
+        let name = table
+            .get("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or("unnamed")
+            .to_owned();
+
+        let kind = Self::KINDS
+            .iter()
+            .find(|(field, _)| table.contains_key(*field))
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} step has none of the {} fields",
+                    name,
+                    Self::KINDS.map(|(field, _)| field).join(", ")
+                )
+            })?;
+
+        let policy = StepPolicy::FIELDS
+            .iter()
+            .filter_map(|field| table.remove(*field).map(|value| (field.to_string(), value)))
+            .collect::<toml::Table>();
+        let mut common = StepCommon::FIELDS
+            .iter()
+            .filter_map(|field| table.remove(*field).map(|value| (field.to_string(), value)))
+            .collect::<toml::Table>();
+        common.insert("policy".to_owned(), toml::Value::Table(policy));
+        table.insert("kind".to_owned(), toml::Value::String(kind.to_owned()));
+
+        let step_err = |err: toml::de::Error| anyhow!("{} {} step: {}", name, kind, err.message());
+        Ok(Self {
+            common: StepCommon::deserialize(toml::Value::Table(common)).map_err(step_err)?,
+            kind: StepKind::deserialize(toml::Value::Table(table)).map_err(|err| {
+                // the kind only knows its own fields, the common ones are valid as well
+                if !err.message().starts_with("unknown field") {
+                    return step_err(err);
+                }
+                anyhow!(
+                    "{} {} step: {}, or one of the fields of all steps: {}",
+                    name,
+                    kind,
+                    err.message(),
+                    StepCommon::FIELDS
+                        .iter()
+                        .chain(StepPolicy::FIELDS.iter())
+                        .map(|field| format!("`{}`", field))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ModuleStep {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        d.deserialize_map(StepVisitor)
+    }
+}
+
+/// Converts the step while its table is being deserialized, so that errors are reported
+/// at the step and not at the start of the steps list
+struct StepVisitor;
+
+impl<'de> de::Visitor<'de> for StepVisitor {
+    type Value = ModuleStep;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a step table")
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let table = toml::Table::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        ModuleStep::from_table(table).map_err(de::Error::custom)
+    }
+}
+
+impl StepCommon {
+    const FIELDS: [&'static str; 4] = ["name", "check", "when", "parallel"];
+}
+
 impl ModuleConfig {
+    /// Step names used by more than one step, such steps would share their install state
+    pub fn duplicate_steps(&self) -> Vec<&str> {
+        let mut duplicates = vec![];
+        for (idx, step) in self.steps.iter().enumerate() {
+            let name = step.common.name.as_str();
+            if self.steps[..idx]
+                .iter()
+                .any(|prev| prev.common.name == name)
+                && !duplicates.contains(&name)
+            {
+                duplicates.push(name);
+            }
+        }
+
+        duplicates
+    }
+
     pub fn parse_dir(dir_path: &PathBuf) -> Result<HashMap<String, Self>> {
+        let mut modules = HashMap::<String, Self>::new();
+        let mut sources = HashMap::<String, PathBuf>::new();
+        for path in Self::module_files(dir_path)? {
+            let module_config = Self::parse(&path)?;
+
+            if let Some(source) = sources.get(&module_config.name) {
+                bail!(
+                    "duplicate {} module in {} and {}",
+                    module_config.name,
+                    source.display(),
+                    path.display()
+                );
+            }
+            sources.insert(module_config.name.clone(), path);
+            modules.insert(module_config.name.clone(), module_config);
+        }
+
+        Ok(modules)
+    }
+
+    /// Files of the modules_definitions directory, sorted by name
+    pub fn module_files(dir_path: &PathBuf) -> Result<Vec<PathBuf>> {
         if !dir_path.is_dir() {
             bail!("modules_definitions should be a directory path");
         }
 
-        let mut modules = HashMap::<String, Self>::new();
+        let mut files = vec![];
         for entry in fs::read_dir(dir_path)? {
             let path = entry?.path();
 
@@ -43,11 +374,11 @@ impl ModuleConfig {
                 continue;
             }
 
-            let module_config = Self::parse(&path)?;
-            modules.insert(module_config.name.clone(), module_config);
+            files.push(path);
         }
+        files.sort();
 
-        Ok(modules)
+        Ok(files)
     }
 
     pub fn parse(input_path: &PathBuf) -> Result<Self> {
@@ -59,8 +390,100 @@ impl ModuleConfig {
         }
 
         let config_str = fs::read_to_string(input_path)?;
-        let config: Self = toml::from_str(&config_str)?;
+        let config: Self = toml::from_str(&config_str)
+            .map_err(|err| anyhow!("{}: {}", input_path.display(), err))?;
 
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(toml: &str) -> Result<ModuleStep> {
+        ModuleStep::from_table(toml::from_str(toml).expect("valid toml"))
+    }
+
+    #[test]
+    fn from_table_prefers_tool_over_packages() {
+        let step = step("name = \"cli\"\ntool = \"cargo\"\npackages = [\"bat\"]").unwrap();
+
+        assert!(matches!(step.kind, StepKind::Tool { .. }));
+    }
+
+    #[test]
+    fn from_table_splits_common_and_policy_fields() {
+        let step = step("name = \"s\"\nscript = \"true\"\nparallel = true\nretries = 2").unwrap();
+
+        assert_eq!(step.common.name, "s");
+        assert!(step.common.parallel);
+        assert_eq!(step.common.policy.retries, Some(2));
+        assert!(matches!(step.kind, StepKind::Script { .. }));
+    }
+
+    #[test]
+    fn from_table_rejects_steps_without_kind() {
+        let err = step("name = \"s\"\ncheck = \"true\"").unwrap_err();
+
+        assert!(err.to_string().starts_with("s step has none of the tool, "));
+    }
+
+    #[test]
+    fn from_table_rejects_unknown_fields() {
+        let err = step("name = \"s\"\nscript = \"true\"\nbogus = 1").unwrap_err();
+
+        assert!(err.to_string().contains("unknown field `bogus`"));
+    }
+
+    #[test]
+    fn duplicate_steps_lists_each_repeated_name_once() {
+        let module: ModuleConfig = toml::from_str(
+            r#"
+            name = "m"
+            steps = [
+                { name = "s", script = "true" },
+                { name = "t", script = "true" },
+                { name = "s", script = "true" },
+                { name = "s", script = "true" },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(module.duplicate_steps(), ["s"]);
+    }
+
+    #[test]
+    fn archive_packages_accept_urls_and_tables() {
+        let step = step(
+            r#"
+            name = "a"
+            extract_to = "/opt"
+            packages = ["https://example.com/a.zip", { url = "https://example.com/b.zip", sha256 = "ab" }]
+            "#,
+        )
+        .unwrap();
+
+        let StepKind::Archive { packages, .. } = step.kind else {
+            panic!("expected an archive step");
+        };
+        assert_eq!(packages[0].sha256, None);
+        assert_eq!(packages[1].url, "https://example.com/b.zip");
+        assert_eq!(packages[1].sha256.as_deref(), Some("ab"));
+    }
+
+    #[test]
+    fn archive_packages_reject_misspelled_keys() {
+        let err = step(
+            r#"
+            name = "a"
+            extract_to = "/opt"
+            packages = [{ url = "https://example.com/a.zip", sha265 = "ab" }]
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("unknown field `sha265`"));
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+
+/// Profile definition, either a plain list of modules or a table composing other profiles
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub extends: Vec<String>,
+    pub modules: Vec<String>,
+    /// Modules inherited from `extends` that are left out of this profile
+    pub exclude: Vec<String>,
+    /// Overrides global `variables` and those inherited from `extends`
+    pub variables: HashMap<String, String>,
+    /// Scripts run before and after installing the profile, after those of `extends`
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+struct ProfileDefinition(ProfileConfig);
+
+impl<'de> Deserialize<'de> for ProfileDefinition {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = toml::Value::deserialize(d)?;
+        let profile = if value.is_array() {
+            Vec::<String>::deserialize(value).map(|modules| ProfileConfig {
+                modules,
+                ..Default::default()
+            })
+        } else {
+            ProfileConfig::deserialize(value)
+        };
+
+        profile
+            .map(Self)
+            .map_err(|err| de::Error::custom(err.message()))
+    }
+}
+
+impl ProfileConfig {
+    pub fn parse_profiles<'de, D>(d: D) -> Result<HashMap<String, Self>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let profiles = HashMap::<String, ProfileDefinition>::deserialize(d)?;
+        Ok(profiles
+            .into_iter()
+            .map(|(name, profile)| (name, profile.0))
+            .collect())
+    }
+}
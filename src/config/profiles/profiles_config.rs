@@ -1,43 +1,240 @@
-use super::{module_config::ModuleConfig, tools_config::ToolConfig};
-use anyhow::{bail, Result};
+use super::{module_config::ModuleConfig, profile_config::ProfileConfig, tools_config::ToolConfig};
+use anyhow::{anyhow, bail, Result};
 use serde::Deserialize;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env, fs,
     path::{Path, PathBuf},
 };
 
+/// Modules, variables and hooks of a profile with everything inherited through `extends`
+pub struct ExpandedProfile {
+    pub modules: Vec<String>,
+    pub variables: HashMap<String, String>,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
 #[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct ProfilesConfig {
     pub tools_definitions: Option<PathBuf>,
     pub modules_definitions: Option<PathBuf>,
 
     pub default_profile: Option<String>,
 
-    pub profiles: HashMap<String, Vec<String>>,
+    #[serde(deserialize_with = "ProfileConfig::parse_profiles")]
+    pub profiles: HashMap<String, ProfileConfig>,
     #[serde(default)]
     pub tools: HashMap<String, ToolConfig>,
     #[serde(default)]
     pub modules: HashMap<String, ModuleConfig>,
+
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+
+    /// Scripts run when an install run finishes, with its summary in `PDE_*` environment variables
+    #[serde(default)]
+    pub on_success: Vec<String>,
+    #[serde(default)]
+    pub on_failure: Vec<String>,
+    /// Sends a desktop notification when an install run finishes
+    #[serde(default)]
+    pub notify: bool,
+
+    /// Directory containing profiles.toml, paths in profiles are relative to it
+    #[serde(skip)]
+    pub root_dir: PathBuf,
 }
 
 impl ProfilesConfig {
     pub fn parse(input_path: &Option<PathBuf>) -> Result<Self> {
+        let mut config = Self::parse_file(input_path)?;
+        config.parse_tools_definitions()?;
+        config.parse_modules_definitions()?;
+
+        Ok(config)
+    }
+
+    /// Parses profiles.toml alone, without the tools and modules definitions it references
+    pub fn parse_file(input_path: &Option<PathBuf>) -> Result<Self> {
         let config_path = Self::resolve_path(input_path);
         if !config_path.exists() {
             bail!("profiles.toml not found");
         }
 
         let config_str = fs::read_to_string(&config_path)?;
-        let mut config: Self = toml::from_str(&config_str)?;
+        let mut config: Self = toml::from_str(&config_str)
+            .map_err(|err| anyhow!("{}: {}", config_path.display(), err))?;
 
+        let config_path = config_path.canonicalize()?;
         let config_root_path = config_path.parent().expect("config path has parent dir");
-        config.parse_tools_definitions(&config_root_path)?;
-        config.parse_modules_definitions(&config_root_path)?;
+        config.root_dir = config_root_path.to_owned();
 
         Ok(config)
     }
 
+    pub fn tools_path(&self) -> Option<PathBuf> {
+        self.tools_definitions
+            .as_ref()
+            .map(|path| self.root_dir.join(path))
+    }
+
+    pub fn modules_path(&self) -> Option<PathBuf> {
+        self.modules_definitions
+            .as_ref()
+            .map(|path| self.root_dir.join(path))
+    }
+
+    /// Selected profiles, falling back to `default_profile` when none are given
+    pub fn profile_names(&self, selected: &[String]) -> Result<Vec<String>> {
+        if !selected.is_empty() {
+            return Ok(selected.to_vec());
+        }
+
+        let default_profile = self.default_profile.clone().ok_or(
+            anyhow!("profile is not selected. Either provide it as cli arg or define default_profile field in profiles config")
+        )?;
+
+        Ok(vec![default_profile])
+    }
+
+    /// Modules of the given profiles together with their transitive `requires`,
+    /// each listed once and after all of its dependencies
+    pub fn resolve_modules(&self, profile_names: &[String]) -> Result<Vec<&ModuleConfig>> {
+        let mut module_names = vec![];
+        for profile_name in profile_names {
+            module_names.extend(self.expand_profile(profile_name)?.modules);
+        }
+
+        self.resolve_module_names(&module_names)
+    }
+
+    pub fn expand_profile(&self, name: &str) -> Result<ExpandedProfile> {
+        self.visit_profile(name, &mut vec![])
+    }
+
+    fn visit_profile(&self, name: &str, path: &mut Vec<String>) -> Result<ExpandedProfile> {
+        if path.iter().any(|profile| profile == name) {
+            bail!(
+                "profile inheritance cycle: {} -> {}",
+                path.join(" -> "),
+                name
+            );
+        }
+
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            if path.is_empty() {
+                anyhow!("'{}' profile defintion not found", name)
+            } else {
+                anyhow!(
+                    "'{}' profile defintion not found (extended by {})",
+                    name,
+                    path.join(" -> ")
+                )
+            }
+        })?;
+
+        let mut expanded = ExpandedProfile {
+            modules: vec![],
+            variables: HashMap::new(),
+            before: vec![],
+            after: vec![],
+        };
+
+        path.push(name.to_owned());
+        for parent in &profile.extends {
+            let parent = self.visit_profile(parent, path)?;
+            for module in parent.modules {
+                if !expanded.modules.contains(&module) {
+                    expanded.modules.push(module);
+                }
+            }
+            expanded.variables.extend(parent.variables);
+            extend_unique(&mut expanded.before, parent.before);
+            extend_unique(&mut expanded.after, parent.after);
+        }
+        path.pop();
+
+        expanded
+            .modules
+            .retain(|module| !profile.exclude.contains(module));
+        for module in &profile.modules {
+            if !expanded.modules.contains(module) {
+                expanded.modules.push(module.clone());
+            }
+        }
+        expanded.variables.extend(profile.variables.clone());
+        extend_unique(&mut expanded.before, profile.before.clone());
+        extend_unique(&mut expanded.after, profile.after.clone());
+
+        Ok(expanded)
+    }
+
+    /// The given modules together with their transitive `requires`, ordered like
+    /// `resolve_modules`
+    pub fn resolve_module_names(&self, module_names: &[String]) -> Result<Vec<&ModuleConfig>> {
+        let mut resolved = vec![];
+        let mut visited = HashSet::new();
+        let mut path = vec![];
+
+        for module in module_names {
+            self.visit_module(module, &mut path, &mut visited, &mut resolved)?;
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn find_modules(&self, names: &[String]) -> Result<Vec<&ModuleConfig>> {
+        names
+            .iter()
+            .map(|name| {
+                self.modules
+                    .get(name)
+                    .ok_or(anyhow!("{} module not found", name))
+            })
+            .collect()
+    }
+
+    fn visit_module<'a>(
+        &'a self,
+        name: &str,
+        path: &mut Vec<String>,
+        visited: &mut HashSet<String>,
+        resolved: &mut Vec<&'a ModuleConfig>,
+    ) -> Result<()> {
+        if visited.contains(name) {
+            return Ok(());
+        }
+
+        if path.iter().any(|module| module == name) {
+            bail!("module dependency cycle: {} -> {}", path.join(" -> "), name);
+        }
+
+        let module = self.modules.get(name).ok_or_else(|| {
+            if path.is_empty() {
+                anyhow!("{} module not found", name)
+            } else {
+                anyhow!(
+                    "{} module not found (required by {})",
+                    name,
+                    path.join(" -> ")
+                )
+            }
+        })?;
+
+        path.push(name.to_owned());
+        for dependency in &module.requires {
+            self.visit_module(dependency, path, visited, resolved)?;
+        }
+        path.pop();
+
+        visited.insert(name.to_owned());
+        resolved.push(module);
+
+        Ok(())
+    }
+
     fn resolve_path(input_path: &Option<PathBuf>) -> PathBuf {
         if let Some(path) = input_path {
             return path.to_owned();
@@ -51,9 +248,9 @@ impl ProfilesConfig {
             })
     }
 
-    fn parse_tools_definitions(&mut self, config_root: &Path) -> Result<()> {
-        if let Some(path) = &self.tools_definitions {
-            let tools = ToolConfig::parse(&config_root.join(path))?;
+    fn parse_tools_definitions(&mut self) -> Result<()> {
+        if let Some(path) = self.tools_path() {
+            let tools = ToolConfig::parse(&path)?;
 
             self.tools.extend(tools);
         }
@@ -61,13 +258,193 @@ impl ProfilesConfig {
         Ok(())
     }
 
-    fn parse_modules_definitions(&mut self, config_root: &Path) -> Result<()> {
-        if let Some(path) = &self.modules_definitions {
-            let modules = ModuleConfig::parse_dir(&config_root.join(path))?;
+    fn parse_modules_definitions(&mut self) -> Result<()> {
+        if let Some(path) = self.modules_path() {
+            let modules = ModuleConfig::parse_dir(&path)?;
 
+            if let Some(name) = modules.keys().find(|name| self.modules.contains_key(*name)) {
+                bail!(
+                    "{} module is defined both in profiles.toml and in {}",
+                    name,
+                    path.display()
+                );
+            }
             self.modules.extend(modules);
         }
 
         Ok(())
     }
 }
+
+/// Hooks of profiles inherited more than once run a single time
+fn extend_unique(target: &mut Vec<String>, values: Vec<String>) {
+    for value in values {
+        if !target.contains(&value) {
+            target.push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULES: &str = r#"
+        [modules.base]
+        name = "base"
+        steps = []
+
+        [modules.lib]
+        name = "lib"
+        requires = ["base"]
+        steps = []
+
+        [modules.app]
+        name = "app"
+        requires = ["lib", "base"]
+        steps = []
+
+        [modules.extra]
+        name = "extra"
+        steps = []
+    "#;
+
+    fn config(profiles: &str) -> ProfilesConfig {
+        toml::from_str(&format!("{}\n{}", profiles, MODULES)).expect("valid profiles config")
+    }
+
+    fn resolve(config: &ProfilesConfig, profiles: &[&str]) -> Result<Vec<String>> {
+        let profiles: Vec<String> = profiles.iter().map(|name| name.to_string()).collect();
+        Ok(config
+            .resolve_modules(&profiles)?
+            .into_iter()
+            .map(|module| module.name.clone())
+            .collect())
+    }
+
+    #[test]
+    fn resolve_modules_orders_dependencies_first() {
+        let config = config("[profiles]\ndesk = [\"app\", \"extra\"]");
+
+        let modules = resolve(&config, &["desk"]).unwrap();
+
+        assert_eq!(modules, ["base", "lib", "app", "extra"]);
+    }
+
+    #[test]
+    fn resolve_modules_lists_shared_modules_once() {
+        let config = config("[profiles]\none = [\"lib\"]\ntwo = [\"app\", \"base\"]");
+
+        let modules = resolve(&config, &["one", "two"]).unwrap();
+
+        assert_eq!(modules, ["base", "lib", "app"]);
+    }
+
+    #[test]
+    fn resolve_modules_rejects_dependency_cycles() {
+        let config: ProfilesConfig = toml::from_str(
+            r#"
+            [profiles]
+            desk = ["a"]
+
+            [modules.a]
+            name = "a"
+            requires = ["b"]
+            steps = []
+
+            [modules.b]
+            name = "b"
+            requires = ["a"]
+            steps = []
+            "#,
+        )
+        .unwrap();
+
+        let err = resolve(&config, &["desk"]).unwrap_err();
+
+        assert_eq!(err.to_string(), "module dependency cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn resolve_modules_reports_missing_requirements() {
+        let config: ProfilesConfig = toml::from_str(
+            r#"
+            [profiles]
+            desk = ["a"]
+
+            [modules.a]
+            name = "a"
+            requires = ["missing"]
+            steps = []
+            "#,
+        )
+        .unwrap();
+
+        let err = resolve(&config, &["desk"]).unwrap_err();
+
+        assert_eq!(err.to_string(), "missing module not found (required by a)");
+    }
+
+    #[test]
+    fn expand_profile_inherits_and_excludes_modules() {
+        let config = config(
+            r#"
+            [profiles]
+            base = ["base", "lib"]
+            desk = { extends = ["base"], exclude = ["lib"], modules = ["extra"] }
+            "#,
+        );
+
+        let profile = config.expand_profile("desk").unwrap();
+
+        assert_eq!(profile.modules, ["base", "extra"]);
+    }
+
+    #[test]
+    fn expand_profile_overrides_inherited_variables() {
+        let config = config(
+            r#"
+            [profiles]
+            base = { variables = { editor = "vi", shell = "bash" } }
+            desk = { extends = ["base"], variables = { shell = "zsh" } }
+            "#,
+        );
+
+        let profile = config.expand_profile("desk").unwrap();
+
+        assert_eq!(profile.variables["editor"], "vi");
+        assert_eq!(profile.variables["shell"], "zsh");
+    }
+
+    #[test]
+    fn expand_profile_runs_hooks_of_shared_parents_once() {
+        let config = config(
+            r#"
+            [profiles]
+            base = { before = ["snapshot"] }
+            left = { extends = ["base"] }
+            right = { extends = ["base"], before = ["notify"] }
+            desk = { extends = ["left", "right"] }
+            "#,
+        );
+
+        let profile = config.expand_profile("desk").unwrap();
+
+        assert_eq!(profile.before, ["snapshot", "notify"]);
+    }
+
+    #[test]
+    fn expand_profile_rejects_inheritance_cycles() {
+        let config = config(
+            r#"
+            [profiles]
+            a = { extends = ["b"] }
+            b = { extends = ["a"] }
+            "#,
+        );
+
+        let err = config.expand_profile("a").err().unwrap();
+
+        assert_eq!(err.to_string(), "profile inheritance cycle: a -> b -> a");
+    }
+}
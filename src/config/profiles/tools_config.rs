@@ -1,12 +1,25 @@
-use anyhow::{bail, Result};
-use serde::Deserialize;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, path::PathBuf};
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ToolConfig {
     pub cmd: String,
     #[serde(default)]
     pub batching: bool,
+    /// Command that exits 0 when the package passed as its last argument is installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_cmd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uninstall_cmd: Option<String>,
+    /// Defaults for steps using the tool, see `StepPolicy`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
 }
 
 impl ToolConfig {
@@ -19,7 +32,8 @@ impl ToolConfig {
         }
 
         let config_str = fs::read_to_string(input_path)?;
-        let config: HashMap<String, ToolConfig> = toml::from_str(&config_str)?;
+        let config: HashMap<String, ToolConfig> = toml::from_str(&config_str)
+            .map_err(|err| anyhow!("{}: {}", input_path.display(), err))?;
 
         Ok(config)
     }
@@ -13,6 +13,7 @@ use commands::{
     install::{InstallCommand, InstallCommandHandler},
     monitor::{MonitorCommand, MonitorCommandHandler},
     theme::{ThemeCommand, ThemeCommandHandler},
+    uninstall::{UninstallCommand, UninstallCommandHandler},
     volume::{VolumeCommand, VolumeCommandHandler},
 };
 use config::Config;
@@ -31,6 +32,7 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Install(InstallCommand),
+    Uninstall(UninstallCommand),
     #[clap(alias = "app")]
     Application(ApplicationCommand),
     Volume(VolumeCommand),
@@ -44,7 +46,8 @@ fn main() -> Result<()> {
 
     let config = Config::parse(cli.config)?;
     match &cli.command {
-        Commands::Install(cmd) => InstallCommandHandler::create().handle(cmd)?,
+        Commands::Install(cmd) => InstallCommandHandler::create(&config).handle(cmd)?,
+        Commands::Uninstall(cmd) => UninstallCommandHandler::create(&config).handle(cmd)?,
         Commands::Application(cmd) => ApplicationCommandHandler::create().handle(cmd)?,
         Commands::Theme(cmd) => ThemeCommandHandler::create(&config).handle(cmd)?,
         Commands::Volume(cmd) => VolumeCommandHandler::create(&config).handle(cmd)?,
@@ -1,6 +1,11 @@
 pub mod brightness;
 pub mod flatpak;
+pub mod git;
+pub mod host;
+pub mod menu;
 pub mod notification;
+pub mod package_manager;
+pub mod systemd;
 pub mod volume;
 pub mod wallpaper;
 pub mod wallust;
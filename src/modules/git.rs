@@ -0,0 +1,149 @@
+use anyhow::{bail, Result};
+use std::{path::Path, process::Command};
+
+use crate::utils::command_extensions::CommandExtensions;
+
+pub struct Git {}
+
+impl Git {
+    pub fn is_repo(path: &Path) -> bool {
+        path.join(".git").exists()
+    }
+
+    pub fn clone(
+        url: &str,
+        dest: &Path,
+        branch: Option<&str>,
+        depth: Option<u32>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        let mut cmd = Command::new("git");
+        cmd.arg("clone");
+        if let Some(branch) = branch {
+            cmd.args(["--branch", branch]);
+        }
+        if let Some(depth) = depth {
+            cmd.arg("--depth").arg(depth.to_string());
+        }
+        cmd.arg(url).arg(dest);
+        Self::run_logged(&mut cmd, output)
+    }
+
+    pub fn fetch(
+        repo: &Path,
+        rev: Option<&str>,
+        depth: Option<u32>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        let mut cmd = Self::git(repo);
+        cmd.args(["fetch", "--tags"]);
+        if let Some(depth) = depth {
+            cmd.arg("--depth").arg(depth.to_string());
+        }
+        cmd.arg("origin");
+        if let Some(rev) = rev {
+            cmd.arg(rev);
+        }
+        Self::run_logged(&mut cmd, output)
+    }
+
+    pub fn head(repo: &Path) -> Result<String> {
+        Self::rev_parse(repo, "HEAD")
+    }
+
+    pub fn rev_parse(repo: &Path, rev: &str) -> Result<String> {
+        let output = Self::git(repo)
+            .args(["rev-parse", "--verify"])
+            .arg(format!("{}^{{commit}}", rev))
+            .pde_run()?;
+        Ok(String::from_utf8(output)?.trim().to_owned())
+    }
+
+    pub fn has_commit(repo: &Path, rev: &str) -> Result<bool> {
+        Self::git(repo)
+            .args(["rev-parse", "--quiet", "--verify"])
+            .arg(format!("{}^{{commit}}", rev))
+            .pde_succeeds()
+    }
+
+    pub fn current_branch(repo: &Path) -> Result<Option<String>> {
+        let output = Self::git(repo)
+            .args(["branch", "--show-current"])
+            .pde_run()?;
+        let branch = String::from_utf8(output)?.trim().to_owned();
+        Ok(Some(branch).filter(|branch| !branch.is_empty()))
+    }
+
+    pub fn checkout(repo: &Path, rev: &str, output: &(dyn Fn(&str) + Sync)) -> Result<()> {
+        Self::run_logged(Self::git(repo).arg("checkout").arg(rev), output)
+    }
+
+    pub fn checkout_detached(repo: &Path, rev: &str, output: &(dyn Fn(&str) + Sync)) -> Result<()> {
+        Self::run_logged(
+            Self::git(repo).args(["checkout", "--detach"]).arg(rev),
+            output,
+        )
+    }
+
+    pub fn fast_forward(repo: &Path, branch: &str, output: &(dyn Fn(&str) + Sync)) -> Result<()> {
+        Self::run_logged(
+            Self::git(repo)
+                .args(["merge", "--ff-only"])
+                .arg(format!("origin/{}", branch)),
+            output,
+        )
+    }
+
+    /// Url of the `origin` remote, `None` when the repo has none
+    pub fn remote_url(repo: &Path) -> Result<Option<String>> {
+        let output = Self::git(repo)
+            .args(["config", "--get", "remote.origin.url"])
+            .output()?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(Some(String::from_utf8(output.stdout)?.trim().to_owned()))
+    }
+
+    /// Whether there are modified, staged or untracked files
+    pub fn has_changes(repo: &Path) -> Result<bool> {
+        let output = Self::git(repo).args(["status", "--porcelain"]).pde_run()?;
+        Ok(!output.is_empty())
+    }
+
+    /// Whether HEAD or a local branch has commits that no remote branch or tag contains
+    pub fn has_unpushed_commits(repo: &Path) -> Result<bool> {
+        let output = Self::git(repo)
+            .args(["rev-list", "--count", "HEAD", "--branches", "--not"])
+            .args(["--remotes", "--tags"])
+            .pde_run()?;
+        Ok(String::from_utf8(output)?.trim() != "0")
+    }
+
+    pub fn short(sha: &str) -> &str {
+        &sha[..sha.len().min(8)]
+    }
+
+    /// Runs a command changing the repo, passing its stdout and stderr lines to `output`
+    fn run_logged(cmd: &mut Command, output: &(dyn Fn(&str) + Sync)) -> Result<()> {
+        let result = cmd.output()?;
+        let stdout = String::from_utf8_lossy(&result.stdout);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        for line in stdout.lines().chain(stderr.lines()) {
+            output(line);
+        }
+
+        if !result.status.success() {
+            bail!("[git]\n{}", stderr);
+        }
+
+        Ok(())
+    }
+
+    fn git(repo: &Path) -> Command {
+        let mut cmd = Command::new("git");
+        cmd.arg("-C").arg(repo);
+        cmd
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use std::{env, fs, path::Path, process::Command};
+
+use crate::utils::command_extensions::CommandExtensions;
+
+pub struct Host {}
+
+impl Host {
+    pub fn hostname() -> Result<String> {
+        if let Ok(hostname) = fs::read_to_string("/proc/sys/kernel/hostname") {
+            return Ok(hostname.trim().to_owned());
+        }
+
+        let output = Command::new("hostname").pde_run()?;
+        Ok(String::from_utf8(output)?.trim().to_owned())
+    }
+
+    pub fn username() -> Result<String> {
+        if let Ok(user) = env::var("USER") {
+            return Ok(user);
+        }
+
+        let output = Command::new("id").arg("-un").pde_run()?;
+        Ok(String::from_utf8(output)?.trim().to_owned())
+    }
+
+    /// `ID` field of /etc/os-release, e.g. `fedora` or `arch`
+    pub fn distro() -> Result<String> {
+        let os_release = fs::read_to_string("/etc/os-release")?;
+        let id = os_release
+            .lines()
+            .find_map(|line| line.strip_prefix("ID="))
+            .map(|id| id.trim_matches('"').to_owned())
+            .unwrap_or_else(|| "linux".to_owned());
+
+        Ok(id)
+    }
+
+    pub fn has_binary(name: &str) -> bool {
+        if name.contains('/') {
+            return Path::new(name).is_file();
+        }
+
+        env::var_os("PATH")
+            .map(|path| env::split_paths(&path).any(|dir| dir.join(name).is_file()))
+            .unwrap_or(false)
+    }
+
+    pub fn has_battery() -> bool {
+        let Ok(supplies) = fs::read_dir("/sys/class/power_supply") else {
+            return false;
+        };
+
+        supplies.flatten().any(|supply| {
+            fs::read_to_string(supply.path().join("type"))
+                .map(|supply_type| supply_type.trim() == "Battery")
+                .unwrap_or(false)
+        })
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Result};
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    process::{Command, Stdio},
+};
+
+use crate::modules::host::Host;
+
+/// Multi selection menu using rofi in a graphical session, fzf in a terminal and a
+/// plain numbered checklist when neither is available
+pub struct Menu {}
+
+impl Menu {
+    /// Indices of the selected items, in their original order
+    pub fn multi_select(prompt: &str, items: &[String]) -> Result<Vec<usize>> {
+        let graphical =
+            env::var_os("WAYLAND_DISPLAY").is_some() || env::var_os("DISPLAY").is_some();
+
+        let mut selected = if graphical && Host::has_binary("rofi") {
+            Self::rofi(prompt, items)?
+        } else if io::stdin().is_terminal() && Host::has_binary("fzf") {
+            Self::fzf(prompt, items)?
+        } else {
+            Self::checklist(prompt, items)?
+        };
+        selected.sort();
+        selected.dedup();
+
+        Ok(selected)
+    }
+
+    fn rofi(prompt: &str, items: &[String]) -> Result<Vec<usize>> {
+        let output = Self::pipe(
+            Command::new("rofi").args([
+                "-dmenu",
+                "-multi-select",
+                "-i",
+                "-format",
+                "i",
+                "-p",
+                prompt,
+            ]),
+            &items.join("\n"),
+        )?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect())
+    }
+
+    fn fzf(prompt: &str, items: &[String]) -> Result<Vec<usize>> {
+        let input = items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| format!("{}\t{}", idx, item))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let output = Self::pipe(
+            Command::new("fzf")
+                .args(["--multi", "--delimiter", "\t", "--with-nth", "2.."])
+                .arg(format!("--prompt={}> ", prompt))
+                .arg("--header=tab toggles, enter confirms"),
+            &input,
+        )?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| line.split('\t').next()?.parse().ok())
+            .collect())
+    }
+
+    fn checklist(prompt: &str, items: &[String]) -> Result<Vec<usize>> {
+        let mut selected = vec![false; items.len()];
+        loop {
+            for (idx, item) in items.iter().enumerate() {
+                let mark = if selected[idx] { "x" } else { " " };
+                println!("{:>3}. [{}] {}", idx + 1, mark, item);
+            }
+            print!(
+                "{}: numbers to toggle, `a` for all, `n` for none, empty line to confirm: ",
+                prompt
+            );
+            io::stdout().flush()?;
+
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                bail!("selection aborted");
+            }
+            if line.trim().is_empty() {
+                break;
+            }
+
+            for token in line.split_whitespace() {
+                match token {
+                    "a" => selected.fill(true),
+                    "n" => selected.fill(false),
+                    _ => match token.parse::<usize>() {
+                        Ok(number) if (1..=items.len()).contains(&number) => {
+                            selected[number - 1] = !selected[number - 1]
+                        }
+                        _ => println!("ignoring invalid entry {}", token),
+                    },
+                }
+            }
+        }
+
+        Ok((0..items.len()).filter(|idx| selected[*idx]).collect())
+    }
+
+    /// Writes the menu entries to stdin and returns stdout, empty when the menu was
+    /// dismissed
+    fn pipe(cmd: &mut Command, input: &str) -> Result<String> {
+        let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(input.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
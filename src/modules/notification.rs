@@ -41,6 +41,11 @@ impl<'a> Notification<'a> {
         self
     }
 
+    pub fn urgency(mut self, urgency: &str) -> Self {
+        self.urgency = urgency.to_owned();
+        self
+    }
+
     pub fn transient(mut self) -> Self {
         self.transient = true;
         self
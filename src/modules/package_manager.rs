@@ -0,0 +1,153 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, process::Command};
+
+use crate::{
+    config::profiles::tools_config::ToolConfig, modules::host::Host,
+    utils::command_extensions::CommandExtensions,
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Dnf,
+    Apt,
+    Pacman,
+    Zypper,
+    Flatpak,
+    Cargo,
+}
+
+impl PackageManager {
+    /// System package managers in the order they are looked up on PATH
+    const SYSTEM: [Self; 4] = [Self::Dnf, Self::Apt, Self::Pacman, Self::Zypper];
+
+    pub fn detect() -> Option<Self> {
+        Self::SYSTEM
+            .into_iter()
+            .find(|manager| Host::has_binary(manager.binary()))
+    }
+
+    /// Manager whose binary is invoked by the tool command, e.g. dnf for `sudo dnf install -y`
+    pub fn from_cmd(cmd: &str) -> Option<Self> {
+        let words = cmd.split_whitespace().collect::<Vec<_>>();
+        [Self::SYSTEM.as_slice(), &[Self::Flatpak, Self::Cargo]]
+            .concat()
+            .into_iter()
+            .find(|manager| words.contains(&manager.binary()))
+    }
+
+    pub fn is_available(&self) -> bool {
+        Host::has_binary(self.binary())
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Apt => "apt",
+            _ => self.binary(),
+        }
+    }
+
+    /// Packages explicitly installed by the user, without their dependencies
+    pub fn installed_packages(&self) -> Result<Vec<String>> {
+        let (program, args): (&str, &[&str]) = match self {
+            Self::Dnf => (
+                "dnf",
+                &["repoquery", "--userinstalled", "--queryformat", "%{name}\n"],
+            ),
+            Self::Apt => ("apt-mark", &["showmanual"]),
+            Self::Pacman => ("pacman", &["-Qqe"]),
+            Self::Flatpak => ("flatpak", &["list", "--app", "--columns=application"]),
+            Self::Cargo => ("cargo", &["install", "--list"]),
+            Self::Zypper => bail!("listing installed zypper packages is not supported"),
+        };
+
+        let output = String::from_utf8(Command::new(program).args(args).pde_run()?)?;
+        let lines = output.lines().map(str::trim_end);
+        let mut packages: Vec<String> = match self {
+            // `<crate> v<version>:` headers followed by indented binaries
+            Self::Cargo => lines
+                .filter(|line| !line.starts_with(char::is_whitespace))
+                .filter_map(|line| line.split_whitespace().next())
+                .map(str::to_owned)
+                .collect(),
+            _ => lines
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        };
+        packages.sort();
+        packages.dedup();
+
+        Ok(packages)
+    }
+
+    /// Built-in tool definition installing all packages in a single command
+    pub fn tool_config(&self) -> ToolConfig {
+        let (cmd, check_cmd, uninstall_cmd) = match self {
+            Self::Dnf => ("sudo dnf install -y", Some("rpm -q"), "sudo dnf remove -y"),
+            Self::Apt => (
+                "sudo apt-get install -y",
+                Some("dpkg -s"),
+                "sudo apt-get remove -y",
+            ),
+            Self::Pacman => (
+                "sudo pacman -S --needed --noconfirm",
+                Some("pacman -Q"),
+                "sudo pacman -R --noconfirm",
+            ),
+            Self::Zypper => (
+                "sudo zypper --non-interactive install",
+                Some("rpm -q"),
+                "sudo zypper --non-interactive remove",
+            ),
+            Self::Flatpak => (
+                "flatpak install -y --noninteractive flathub",
+                Some("flatpak info"),
+                "flatpak uninstall -y --noninteractive",
+            ),
+            // `cargo install` skips crates that are already installed
+            Self::Cargo => ("cargo install", None, "cargo uninstall"),
+        };
+
+        ToolConfig {
+            cmd: cmd.to_owned(),
+            batching: true,
+            check_cmd: check_cmd.map(str::to_owned),
+            uninstall_cmd: Some(uninstall_cmd.to_owned()),
+            retries: None,
+            retry_delay: None,
+            timeout: None,
+        }
+    }
+
+    /// Maps logical package names through the manager overrides, where an override
+    /// can list several space separated packages or none to skip the package
+    pub fn package_names(
+        &self,
+        packages: &[String],
+        overrides: &HashMap<PackageManager, HashMap<String, String>>,
+    ) -> Vec<String> {
+        let overrides = overrides.get(self);
+        packages
+            .iter()
+            .flat_map(
+                |package| match overrides.and_then(|overrides| overrides.get(package)) {
+                    Some(names) => names.split_whitespace().map(str::to_owned).collect(),
+                    None => vec![package.clone()],
+                },
+            )
+            .collect()
+    }
+
+    fn binary(&self) -> &str {
+        match self {
+            Self::Dnf => "dnf",
+            Self::Apt => "apt-get",
+            Self::Pacman => "pacman",
+            Self::Zypper => "zypper",
+            Self::Flatpak => "flatpak",
+            Self::Cargo => "cargo",
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use std::{env, path::PathBuf, process::Command};
+
+use crate::utils::{command_extensions::CommandExtensions, path_extensions::PathExtensions};
+
+pub struct Systemd {}
+
+impl Systemd {
+    /// `$XDG_CONFIG_HOME/systemd/user`, falling back to `~/.config/systemd/user`
+    pub fn user_unit_dir() -> Result<PathBuf> {
+        let config_dir = match env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from("~/.config").pde_resolve()?,
+        };
+
+        Ok(config_dir.join("systemd/user"))
+    }
+
+    pub fn enabled_user_units() -> Result<Vec<String>> {
+        let output =
+            Self::systemctl(&["list-unit-files", "--state=enabled", "--no-legend"]).pde_run()?;
+
+        Ok(String::from_utf8(output)?
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_owned)
+            .collect())
+    }
+
+    pub fn is_enabled(unit: &str) -> Result<bool> {
+        Self::systemctl(&["is-enabled", "--quiet", unit]).pde_succeeds()
+    }
+
+    pub fn is_active(unit: &str) -> Result<bool> {
+        Self::systemctl(&["is-active", "--quiet", unit]).pde_succeeds()
+    }
+
+    pub fn daemon_reload() -> Result<()> {
+        Self::systemctl(&["daemon-reload"]).pde_run()?;
+        Ok(())
+    }
+
+    pub fn enable(unit: &str) -> Result<()> {
+        Self::systemctl(&["enable", unit]).pde_run()?;
+        Ok(())
+    }
+
+    pub fn disable(unit: &str) -> Result<()> {
+        Self::systemctl(&["disable", unit]).pde_run()?;
+        Ok(())
+    }
+
+    pub fn start(unit: &str) -> Result<()> {
+        Self::systemctl(&["start", unit]).pde_run()?;
+        Ok(())
+    }
+
+    pub fn stop(unit: &str) -> Result<()> {
+        Self::systemctl(&["stop", unit]).pde_run()?;
+        Ok(())
+    }
+
+    pub fn restart(unit: &str) -> Result<()> {
+        Self::systemctl(&["restart", unit]).pde_run()?;
+        Ok(())
+    }
+
+    fn systemctl(args: &[&str]) -> Command {
+        let mut cmd = Command::new("systemctl");
+        cmd.arg("--user").args(args);
+        cmd
+    }
+}
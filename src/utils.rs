@@ -1,4 +1,6 @@
+pub mod archive_utils;
 pub mod ascii_utils;
 pub mod command_extensions;
+pub mod fs_utils;
 pub mod image_utils;
 pub mod path_extensions;
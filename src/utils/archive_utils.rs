@@ -0,0 +1,361 @@
+use anyhow::{anyhow, bail, Result};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+};
+use tar::EntryType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+}
+
+const EXTENSIONS: [(&str, ArchiveFormat); 8] = [
+    (".zip", ArchiveFormat::Zip),
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tgz", ArchiveFormat::TarGz),
+    (".tar.xz", ArchiveFormat::TarXz),
+    (".txz", ArchiveFormat::TarXz),
+    (".tar.zst", ArchiveFormat::TarZst),
+    (".tzst", ArchiveFormat::TarZst),
+    (".tar", ArchiveFormat::Tar),
+];
+
+impl ArchiveFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        EXTENSIONS
+            .iter()
+            .find(|(ext, _)| name.ends_with(ext))
+            .map(|(_, format)| *format)
+    }
+
+    pub fn from_magic_bytes(content: &[u8]) -> Option<Self> {
+        if content.starts_with(b"PK\x03\x04") || content.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if content.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if content.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::TarXz)
+        } else if content.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else if content.len() > 262 && &content[257..262] == b"ustar" {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn detect(name: &str, content: &[u8]) -> Result<Self> {
+        Self::from_name(name)
+            .or_else(|| Self::from_magic_bytes(content))
+            .ok_or(anyhow!("unsupported archive format: {}", name))
+    }
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// File name without its archive extension
+pub fn archive_stem(name: &str) -> &str {
+    EXTENSIONS
+        .iter()
+        .find_map(|(ext, _)| name.strip_suffix(ext))
+        .unwrap_or(name)
+}
+
+pub fn extract_archive(
+    content: Vec<u8>,
+    format: ArchiveFormat,
+    target_dir: &Path,
+    strip_components: usize,
+) -> Result<()> {
+    match format {
+        ArchiveFormat::Zip => extract_zip(content, target_dir, strip_components),
+        ArchiveFormat::Tar => extract_tar(Cursor::new(content), target_dir, strip_components),
+        ArchiveFormat::TarGz => extract_tar(
+            GzDecoder::new(Cursor::new(content)),
+            target_dir,
+            strip_components,
+        ),
+        ArchiveFormat::TarXz => {
+            let mut decompressed = vec![];
+            lzma_rs::xz_decompress(&mut Cursor::new(content), &mut decompressed)?;
+            extract_tar(Cursor::new(decompressed), target_dir, strip_components)
+        }
+        ArchiveFormat::TarZst => extract_tar(
+            zstd::Decoder::new(Cursor::new(content))?,
+            target_dir,
+            strip_components,
+        ),
+    }
+}
+
+fn extract_zip(content: Vec<u8>, target_dir: &Path, strip_components: usize) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content))?;
+
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        let Some(path) = entry
+            .enclosed_name()
+            .and_then(|path| strip_path(&path, strip_components))
+        else {
+            continue;
+        };
+        let out_path = target_dir.join(path);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path)?;
+            continue;
+        }
+
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&out_path)?)?;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&out_path, fs::Permissions::from_mode(mode))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_tar(reader: impl Read, target_dir: &Path, strip_components: usize) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    fs::create_dir_all(target_dir)?;
+    let root = target_dir.canonicalize()?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(path) = strip_path(&entry.path()?, strip_components) else {
+            continue;
+        };
+        let out_path = contained_path(&root, &path)?;
+
+        match entry.header().entry_type() {
+            EntryType::Symlink => {
+                let target = entry.link_name()?.unwrap_or_default();
+                if !is_contained_link(&path, &target) {
+                    bail!(
+                        "{} links outside the archive: {}",
+                        path.display(),
+                        target.display()
+                    );
+                }
+            }
+            EntryType::Link => {
+                // Hard link names are archive paths, unpack would resolve them from the cwd
+                let target = entry.link_name()?.unwrap_or_default();
+                let Some(source) = strip_path(&target, strip_components) else {
+                    bail!(
+                        "{} links outside the archive: {}",
+                        path.display(),
+                        target.display()
+                    );
+                };
+                fs::hard_link(contained_path(&root, &source)?, &out_path)?;
+                continue;
+            }
+            _ => {}
+        }
+
+        entry.unpack(&out_path)?;
+    }
+
+    Ok(())
+}
+
+/// Joins an archive path to root, failing when an extracted symlink redirects it outside root
+fn contained_path(root: &Path, path: &Path) -> Result<PathBuf> {
+    let out_path = root.join(path);
+    let parent = out_path.parent().unwrap_or(root);
+    let existing = parent
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or(root)
+        .canonicalize()?;
+    if !existing.starts_with(root) {
+        bail!("{} is outside the extraction directory", path.display());
+    }
+    fs::create_dir_all(parent)?;
+
+    // Never write through a symlink left by an earlier entry
+    if out_path.is_symlink() {
+        fs::remove_file(&out_path)?;
+    }
+
+    Ok(out_path)
+}
+
+/// Whether a symlink at path with the given target stays inside the extraction directory
+fn is_contained_link(path: &Path, target: &Path) -> bool {
+    let mut depth = path.components().count() - 1;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Drops leading path components, rejecting paths that would escape the target dir
+fn strip_path(path: &Path, strip_components: usize) -> Option<PathBuf> {
+    if path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+
+    let stripped: PathBuf = path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .skip(strip_components)
+        .collect();
+
+    if stripped.as_os_str().is_empty() {
+        None
+    } else {
+        Some(stripped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_path_drops_leading_components() {
+        let stripped = strip_path(Path::new("./tool-1.0/bin/tool"), 1);
+
+        assert_eq!(stripped, Some(PathBuf::from("bin/tool")));
+    }
+
+    #[test]
+    fn strip_path_skips_fully_stripped_entries() {
+        assert_eq!(strip_path(Path::new("tool-1.0/"), 1), None);
+    }
+
+    #[test]
+    fn strip_path_rejects_escaping_paths() {
+        assert_eq!(strip_path(Path::new("../outside"), 0), None);
+        assert_eq!(strip_path(Path::new("tool/../../outside"), 1), None);
+        assert_eq!(strip_path(Path::new("/etc/passwd"), 0), None);
+    }
+
+    enum TestEntry<'a> {
+        File(&'a str),
+        Symlink(&'a str, &'a str),
+        HardLink(&'a str, &'a str),
+    }
+
+    fn tarball(entries: &[TestEntry]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            let (path, content) = match entry {
+                TestEntry::File(path) => {
+                    header.set_size(4);
+                    (path, &b"pde\n"[..])
+                }
+                TestEntry::Symlink(path, target) | TestEntry::HardLink(path, target) => {
+                    header.set_entry_type(match entry {
+                        TestEntry::Symlink(..) => EntryType::Symlink,
+                        _ => EntryType::Link,
+                    });
+                    header.set_size(0);
+                    header.set_link_name(target).unwrap();
+                    (path, &b""[..])
+                }
+            };
+            builder.append_data(&mut header, path, content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pde-archive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn extract_tar_keeps_links_inside_the_archive() {
+        let dir = scratch_dir("links");
+        let content = tarball(&[
+            TestEntry::File("tool-1.0/lib/tool"),
+            TestEntry::Symlink("tool-1.0/bin/tool", "../lib/tool"),
+            TestEntry::HardLink("tool-1.0/bin/tool-copy", "tool-1.0/lib/tool"),
+        ]);
+
+        extract_archive(content, ArchiveFormat::Tar, &dir.join("out"), 1).unwrap();
+
+        assert_eq!(fs::read(dir.join("out/bin/tool")).unwrap(), b"pde\n");
+        assert_eq!(fs::read(dir.join("out/bin/tool-copy")).unwrap(), b"pde\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_tar_rejects_symlinks_leaving_the_archive() {
+        let dir = scratch_dir("symlink");
+        let outside = dir.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let content = tarball(&[
+            TestEntry::Symlink("evil/esc", outside.to_str().unwrap()),
+            TestEntry::File("evil/esc/pwned"),
+        ]);
+
+        let result = extract_archive(content, ArchiveFormat::Tar, &dir.join("out"), 0);
+
+        assert!(result.is_err());
+        assert!(!outside.join("pwned").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_tar_rejects_writes_through_chained_symlinks() {
+        let dir = scratch_dir("chain");
+        let content = tarball(&[
+            TestEntry::Symlink("here", "."),
+            TestEntry::Symlink("here/up", ".."),
+            TestEntry::File("here/up/pwned"),
+        ]);
+
+        let result = extract_archive(content, ArchiveFormat::Tar, &dir.join("out"), 0);
+
+        assert!(result.is_err());
+        assert!(!dir.join("pwned").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_tar_rejects_hard_links_leaving_the_archive() {
+        let dir = scratch_dir("hardlink");
+        fs::write(dir.join("secret"), "secret").unwrap();
+        let secret = dir.join("secret");
+        let content = tarball(&[TestEntry::HardLink("evil", secret.to_str().unwrap())]);
+
+        let result = extract_archive(content, ArchiveFormat::Tar, &dir.join("out"), 0);
+
+        assert!(result.is_err());
+        assert!(!dir.join("out/evil").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
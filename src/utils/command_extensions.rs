@@ -1,9 +1,23 @@
 use anyhow::{anyhow, bail, Result};
-use std::process::Command;
+use std::{
+    io::{self, BufRead, BufReader},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
 
 pub trait CommandExtensions {
     fn pde_run(&mut self) -> Result<Vec<u8>>;
 
+    fn pde_succeeds(&mut self) -> Result<bool>;
+
+    fn pde_run_prefixed(
+        &mut self,
+        prefix: &str,
+        timeout: Option<Duration>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()>;
+
     fn is_running(process: &str) -> Result<bool>;
 
     fn killall_if_running(process: &str) -> Result<()>;
@@ -27,6 +41,92 @@ impl CommandExtensions for Command {
         bail!("[{}]\n{}", program, &err_out)
     }
 
+    fn pde_succeeds(&mut self) -> Result<bool> {
+        let status = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+
+        Ok(status.success())
+    }
+
+    /// Prints stdout line by line prefixed with `[prefix]`, so that output of
+    /// concurrent commands stays attributable. Every stdout and stderr line and
+    /// the exit status are passed to `output` as well.
+    /// Once the timeout elapses the command is killed together with all of its
+    /// descendants. It stays in the foreground process group, so prompts like the
+    /// `sudo` password one keep reading from the terminal
+    fn pde_run_prefixed(
+        &mut self,
+        prefix: &str,
+        timeout: Option<Duration>,
+        output: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        let mut child = self.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let (status, err_out) = thread::scope(|scope| {
+            let stderr_reader = scope.spawn(|| {
+                let mut err_out = String::new();
+                for line in BufReader::new(stderr).lines() {
+                    let line = line?;
+                    output(&line);
+                    err_out.push_str(&line);
+                    err_out.push('\n');
+                }
+                Ok::<_, io::Error>(err_out)
+            });
+            let stdout_reader = scope.spawn(|| {
+                for line in BufReader::new(stdout).lines() {
+                    let line = line?;
+                    println!("[{}] {}", prefix, line);
+                    output(&line);
+                }
+                Ok::<_, io::Error>(())
+            });
+
+            let status = match deadline {
+                Some(deadline) => loop {
+                    if let Some(status) = child.try_wait()? {
+                        break Some(status);
+                    }
+                    if Instant::now() >= deadline {
+                        // processes that exited in the meantime make kill fail
+                        Command::new("kill")
+                            .args(["-s", "KILL", "--"])
+                            .args(process_tree(child.id()))
+                            .pde_succeeds()?;
+                        child.wait()?;
+                        break None;
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                },
+                None => Some(child.wait()?),
+            };
+
+            stdout_reader.join().expect("stdout reader panicked")?;
+            let err_out = stderr_reader.join().expect("stderr reader panicked")?;
+            Ok::<_, anyhow::Error>((status, err_out))
+        })?;
+
+        let program = self.get_program().to_str().unwrap_or("");
+        let Some(status) = status else {
+            let timeout = timeout.expect("only commands with a timeout are killed");
+            output(&format!("killed after {}s timeout", timeout.as_secs()));
+            bail!("[{}] killed after {}s timeout", program, timeout.as_secs());
+        };
+
+        output(&status.to_string());
+        if status.success() {
+            return Ok(());
+        }
+
+        bail!("[{}]\n{}", program, &err_out)
+    }
+
     fn is_running(process: &str) -> Result<bool> {
         let out = Command::new("pgrep").arg(process).output()?;
         let exit_code = out
@@ -67,3 +167,18 @@ impl CommandExtensions for Command {
         Ok(cmd)
     }
 }
+
+/// Ids of the process and all of its descendants, parents first
+fn process_tree(pid: u32) -> Vec<String> {
+    let mut pids = vec![pid.to_string()];
+    let mut idx = 0;
+    while idx < pids.len() {
+        if let Ok(out) = Command::new("pgrep").args(["-P", &pids[idx]]).output() {
+            let children = String::from_utf8_lossy(&out.stdout);
+            pids.extend(children.split_whitespace().map(str::to_owned));
+        }
+        idx += 1;
+    }
+
+    pids
+}
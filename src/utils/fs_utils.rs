@@ -0,0 +1,69 @@
+use anyhow::Result;
+use std::{fs, path::Path};
+
+/// Whether anything, including a dangling symlink, exists at the path
+pub fn path_exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+pub fn is_symlink_to(link: &Path, target: &Path) -> bool {
+    fs::read_link(link)
+        .map(|destination| destination == target)
+        .unwrap_or(false)
+}
+
+pub fn remove_path(path: &Path) -> Result<()> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+pub fn copy_recursive(source: &Path, target: &Path) -> Result<()> {
+    if source.is_dir() {
+        fs::create_dir_all(target)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(source, target)?;
+    }
+
+    Ok(())
+}
+
+/// Compares files by content and directories by their entries, recursively
+pub fn same_content(left: &Path, right: &Path) -> Result<bool> {
+    if left.is_dir() != right.is_dir() {
+        return Ok(false);
+    }
+
+    if !left.is_dir() {
+        return Ok(fs::read(left)? == fs::read(right)?);
+    }
+
+    let mut left_entries = fs::read_dir(left)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut right_entries = fs::read_dir(right)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    left_entries.sort();
+    right_entries.sort();
+    if left_entries != right_entries {
+        return Ok(false);
+    }
+
+    for name in left_entries {
+        if !same_content(&left.join(&name), &right.join(&name))? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}